@@ -0,0 +1,888 @@
+use crate::cli::{
+    self, BumpArgs, ChartArgs, Cli, Commands, GraphArgs, ListArgs, ManArgs, NukeArgs, OnArgs,
+    RenderArgs, SetArgs, Shell, StatsArgs, WatchArgs,
+};
+use crate::clock;
+use crate::config::{self, Config, Format};
+use crate::daemon;
+use crate::database::Connection;
+use crate::gauge::Gauge;
+use crate::git::Repo;
+use crate::history::{self, Entry};
+use crate::hooks::{Hook, Trigger};
+use crate::http;
+use crate::models::{Counter, Kind};
+use crate::template;
+use crate::top;
+use crate::version::{Calver, Semver};
+use anyhow::{anyhow, bail, Context, Result};
+use clap::builder::StyledStr;
+use clap::{FromArgMatches, ValueEnum};
+use clap_complete::env::Shells;
+use dirs::home_dir;
+use prettytable::{row, Table};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::thread::sleep;
+use std::time::Duration;
+
+const DATABASE_FILE: &str = "tally.db";
+const DATA_DIR: &str = ".tally";
+
+/// Where tally keeps its files and what the user configured.
+struct Settings {
+    config: Config,
+    default_database: PathBuf,
+}
+
+impl Settings {
+    fn load() -> Result<Settings> {
+        let home: PathBuf = home_dir().ok_or_else(|| anyhow!("couldn't get $HOME directory"))?;
+        let data_dir = home.join(DATA_DIR);
+        let config_path = match std::env::var_os("TALLY_CONFIG") {
+            Some(path) => PathBuf::from(path),
+            None => data_dir.join(config::CONFIG_FILE),
+        };
+        let config = Config::load(&config_path)?;
+        let default_database = config
+            .database
+            .clone()
+            .unwrap_or_else(|| data_dir.join(DATABASE_FILE));
+        Ok(Settings {
+            config,
+            default_database,
+        })
+    }
+
+    /// Database used when `--database` wasn't parsed, honouring the
+    /// environment variable clap would otherwise read.
+    fn completion_database(&self) -> PathBuf {
+        match std::env::var_os("TALLY_DATABASE") {
+            Some(path) => config::expand_tilde(Path::new(&path)),
+            None => self.default_database.clone(),
+        }
+    }
+
+    fn command(&self) -> clap::Command {
+        cli::command(self.config.color, self.completion_database())
+    }
+}
+
+/// Command used to answer shell completion requests.
+pub fn completion_command() -> clap::Command {
+    match Settings::load() {
+        Ok(settings) => settings.command(),
+        Err(_) => cli::command(Default::default(), PathBuf::from(DATABASE_FILE)),
+    }
+}
+
+/// Run tally with `args` (including the binary name), writing normal output
+/// to `stdout` and diagnostics to `stderr`.
+pub fn run<I, T>(args: I, stdout: &mut dyn Write, stderr: &mut dyn Write) -> ExitCode
+where
+    I: IntoIterator<Item = T>,
+    T: Into<OsString> + Clone,
+{
+    let settings = match Settings::load() {
+        Ok(settings) => settings,
+        Err(e) => return report(stderr, e),
+    };
+
+    let parsed = settings
+        .command()
+        .try_get_matches_from(args)
+        .and_then(|matches| Cli::from_arg_matches(&matches));
+    let cli = match parsed {
+        Ok(cli) => cli,
+        Err(e) => {
+            // Help and version go to stdout, real errors to stderr
+            let color = settings.config.color;
+            let rendered = e.render();
+            let _ = if e.use_stderr() {
+                write_styled(
+                    stderr,
+                    &rendered,
+                    color.enabled(std::io::stderr().is_terminal()),
+                )
+            } else {
+                write_styled(
+                    stdout,
+                    &rendered,
+                    color.enabled(std::io::stdout().is_terminal()),
+                )
+            };
+            return ExitCode::from(e.exit_code() as u8);
+        }
+    };
+
+    match execute(&settings, cli, stdout) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => report(stderr, e),
+    }
+}
+
+fn write_styled(out: &mut dyn Write, text: &StyledStr, colored: bool) -> std::io::Result<()> {
+    if colored {
+        write!(out, "{}", text.ansi())
+    } else {
+        write!(out, "{text}")
+    }
+}
+
+fn report(stderr: &mut dyn Write, e: anyhow::Error) -> ExitCode {
+    let _ = writeln!(stderr, "Error: {e:?}");
+    ExitCode::FAILURE
+}
+
+fn execute(settings: &Settings, cli: Cli, out: &mut dyn Write) -> Result<()> {
+    let config = &settings.config;
+
+    // Commands that don't touch the database
+    match &cli.command {
+        Some(Commands::Completions(args)) => return completions(args.shell, out),
+        Some(Commands::Man(args)) => return man(settings, args, out),
+        _ => {}
+    }
+
+    // Reject bad templates before any counter gets created
+    if let Some(Commands::Set(SetArgs {
+        template: Some(template),
+        ..
+    })) = &cli.command
+    {
+        template::validate(template).with_context(|| format!("invalid template '{template}'"))?;
+    }
+
+    // Resolve the database path, flag first then config then ~/.tally
+    let database_path = match &cli.database {
+        Some(path) => config::expand_tilde(path),
+        None => settings.default_database.clone(),
+    };
+    if let Some(parent) = database_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    // Scoped counters are keyed by the checkout we're running in
+    let scope = match cli.scope {
+        Some(scope) => Some(Repo::discover(&std::env::current_dir()?)?.key(scope)?),
+        None => None,
+    };
+
+    // A running daemon already has the database open
+    let strict = config.strict || cli.strict;
+    if let Some(request) = daemon::Request::from_cli(&cli, scope.as_deref(), strict) {
+        if let Some(value) = daemon::send(&daemon::socket_path(&database_path), &request)? {
+            if let (Some(value), false) = (value, cli.quiet) {
+                writeln!(out, "{value}")?;
+            }
+            return Ok(());
+        }
+    }
+
+    let lock_timeout = config.lock_timeout.map(Duration::from_secs);
+    let conn = Connection::open(&database_path.to_string_lossy(), lock_timeout)?;
+
+    // Commands that read the database but don't need a counter
+    match &cli.command {
+        Some(Commands::Graph(args)) => return graph(&conn, args, out),
+        Some(Commands::Watch(args)) => {
+            // Watching mustn't keep everyone else waiting for the lock
+            drop(conn);
+            return watch(&database_path, args, out);
+        }
+        Some(Commands::Top) => {
+            // Nor should the dashboard, which only locks to make a change
+            drop(conn);
+            return top::run(&database_path, lock_timeout, scope.as_deref());
+        }
+        Some(Commands::Render(args)) => {
+            return render(&conn, cli.name.as_deref(), scope.as_deref(), args, out)
+        }
+        Some(Commands::Serve(args)) => {
+            if let Some(address) = &args.http {
+                let token = args.token.as_deref();
+                return http::serve(config, conn, &database_path, lock_timeout, address, token);
+            }
+            let socket = match &args.socket {
+                Some(path) => config::expand_tilde(path),
+                None => daemon::socket_path(&database_path),
+            };
+            return daemon::serve(config, conn, &database_path, lock_timeout, &socket);
+        }
+        _ => {}
+    }
+
+    counter_command(config, &conn, &database_path, cli, scope.as_deref(), out)
+}
+
+/// Run a command that acts on a single counter, creating it first unless
+/// in strict mode.
+pub(crate) fn counter_command(
+    config: &Config,
+    conn: &Connection,
+    database_path: &Path,
+    cli: Cli,
+    scope: Option<&str>,
+    out: &mut dyn Write,
+) -> Result<()> {
+    let name: String = match cli.name {
+        Some(n) => n,
+        None => Counter::get_default(conn.get())?
+            .ok_or_else(|| anyhow!("no default counter set; run 'tally <name> set --default'"))?,
+    };
+
+    // Checking a template shouldn't create the counter it checks
+    if let Some(Commands::CheckTemplate) = &cli.command {
+        return check_template(conn, &name, cli.quiet, out);
+    }
+
+    let is_strict = config.strict || cli.strict;
+    let mut counter = match Counter::get(conn.get(), &name)? {
+        Some(c) => c,
+        None => {
+            // Only an explicit 'set' or 'define' may create counters in strict mode
+            if is_strict && !matches!(cli.command, Some(Commands::Set(_) | Commands::Define(_))) {
+                bail!("counter '{name}' does not exist; create it with 'tally {name} set'");
+            }
+            let mut c = Counter::new(&name);
+            if let Some(step) = config.default_step {
+                c.step = step;
+            }
+            if let Some(template) = &config.default_template {
+                c.template = template.clone();
+            }
+            if let Some(Commands::Bump(_)) = &cli.command {
+                c.kind = Kind::Semver(Semver {
+                    major: 0,
+                    minor: 0,
+                    patch: 0,
+                    pre: None,
+                });
+            }
+            c.insert(conn.get())?;
+            c
+        }
+    };
+    if let Some(scope) = scope {
+        counter = counter.with_scope(conn.get(), scope)?;
+    }
+
+    let output = Output {
+        quiet: cli.quiet,
+        raw: cli.raw,
+    };
+
+    // divert logic to subcommand
+    match cli.command {
+        Some(Commands::Set(args)) => set(conn, &mut counter, args),
+        Some(Commands::Add(args)) => {
+            addable(&counter)?;
+            let before = counter.count;
+            counter.add(args.amount.unwrap_or(counter.step), clock::today()?);
+            save(conn, &counter, before)?;
+            output.counter(conn, &counter, out)
+        }
+        Some(Commands::Sub(args)) => {
+            addable(&counter)?;
+            let before = counter.count;
+            counter.count -= args.amount.unwrap_or(counter.step);
+            save(conn, &counter, before)?;
+            output.counter(conn, &counter, out)
+        }
+        Some(Commands::On(args)) => on(conn, &counter, args, out),
+        Some(Commands::Define(args)) => {
+            counter.kind = Kind::Formula(args.formula);
+            definable(conn, &counter)?;
+            Ok(counter.update(conn.get())?)
+        }
+        Some(Commands::Bump(args)) => {
+            bump(&mut counter, &args)?;
+            counter.update(conn.get())?;
+            output.counter(conn, &counter, out)
+        }
+        Some(Commands::Delete) => match &counter.scope {
+            Some(scope) => Ok(Counter::delete_scope(conn.get(), &counter.name, scope)?),
+            None => Ok(Counter::delete(conn.get(), &counter.name)?),
+        },
+        Some(Commands::List(args)) => list(config, conn, args, out),
+        Some(Commands::Stats(args)) => stats(config, conn, &counter, args, out),
+        Some(Commands::Chart(args)) => chart(conn, &counter, args, out),
+        Some(Commands::Nuke(args)) => nuke(database_path, args, out),
+        Some(Commands::CheckTemplate)
+        | Some(Commands::Graph(_))
+        | Some(Commands::Render(_))
+        | Some(Commands::Watch(_))
+        | Some(Commands::Top)
+        | Some(Commands::Serve(_))
+        | Some(Commands::Completions(_))
+        | Some(Commands::Man(_)) => unreachable!(),
+        None => output.counter(conn, &counter, out),
+    }
+}
+
+/// How a counter is echoed back after a command.
+struct Output {
+    quiet: bool,
+    raw: bool,
+}
+
+impl Output {
+    fn counter(&self, conn: &Connection, counter: &Counter, out: &mut dyn Write) -> Result<()> {
+        if self.quiet {
+            return Ok(());
+        }
+        if self.raw {
+            writeln!(out, "{}", template::display(conn, counter)?)?;
+        } else {
+            writeln!(out, "{}", template::render_counter(conn, counter)?)?;
+        }
+        Ok(())
+    }
+}
+
+/// Fail unless the counter's count can be added to and subtracted from.
+pub(crate) fn addable(counter: &Counter) -> Result<()> {
+    match counter.kind {
+        Kind::Semver(_) => bail!(
+            "counter '{}' holds a version, change it with 'tally {} bump'",
+            counter.name,
+            counter.name
+        ),
+        Kind::Formula(_) => bail!(
+            "counter '{}' is a formula, its value comes from '{}'",
+            counter.name,
+            counter.display()
+        ),
+        Kind::Gauge(_) => bail!(
+            "counter '{}' is a gauge, give it a new value with 'tally {} set <COUNT>'",
+            counter.name,
+            counter.name
+        ),
+        Kind::Counter | Kind::Calver(_) => Ok(()),
+    }
+}
+
+/// Refuse a formula that would read itself, directly or through others.
+fn definable(conn: &Connection, counter: &Counter) -> Result<()> {
+    let mut counters = Counter::get_all(conn.get())?;
+    counters.retain(|c| c.name != counter.name);
+    let mut defined = Counter::new(&counter.name);
+    defined.kind = counter.kind.clone();
+    counters.push(defined);
+    for problem in template::Graph::new(&counters).check(&counter.name) {
+        if let template::Problem::FormulaCycle(names) = &problem {
+            if names.contains(&counter.name) {
+                bail!(
+                    "counter '{}' can't read itself, that makes a {problem}",
+                    counter.name
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Store the counter and note in its history how the count moved from
+/// `before`.
+pub(crate) fn save(conn: &Connection, counter: &Counter, before: i64) -> Result<()> {
+    let delta = counter.count.checked_sub(before).ok_or_else(|| {
+        anyhow!(
+            "counter '{}' can't go from {before} to {} in one change",
+            counter.name,
+            counter.count
+        )
+    })?;
+    let entry = Entry {
+        timestamp: clock::now()?.timestamp(),
+        count: counter.count,
+        delta,
+    };
+    // The count and its history change together or not at all
+    conn.get().execute("BEGIN;")?;
+    let written = counter
+        .update(conn.get())
+        .and_then(|_| history::record(conn.get(), &counter.name, counter.scope.as_deref(), entry));
+    match written {
+        Ok(()) => conn.get().execute("COMMIT;")?,
+        Err(e) => {
+            conn.get().execute("ROLLBACK;")?;
+            return Err(e.into());
+        }
+    }
+
+    // The change is in, so a hook that won't start doesn't undo it
+    for hook in Hook::for_counter(conn.get(), &counter.name)? {
+        if hook.trigger.fires(before, counter.count) {
+            if let Err(e) = hook.spawn(counter.scope.as_deref(), before, counter.count) {
+                eprintln!("tally: {e:#}");
+            }
+        }
+    }
+    Ok(())
+}
+
+fn on(conn: &Connection, counter: &Counter, args: OnArgs, out: &mut dyn Write) -> Result<()> {
+    if let Some(id) = args.remove {
+        if !Hook::remove(conn.get(), &counter.name, id)? {
+            bail!("counter '{}' has no hook {id}", counter.name);
+        }
+        return Ok(());
+    }
+
+    let trigger = match (args.above, args.below, args.equals, args.every) {
+        (Some(n), ..) => Some(Trigger::Above(n)),
+        (_, Some(n), ..) => Some(Trigger::Below(n)),
+        (_, _, Some(n), _) => Some(Trigger::Equals(n)),
+        (.., Some(n)) => Some(Trigger::Every(n)),
+        _ => None,
+    };
+    if let (Some(trigger), Some(command)) = (trigger, &args.run) {
+        Hook::add(conn.get(), &counter.name, trigger, command)?;
+        return Ok(());
+    }
+
+    // Neither adding nor removing, so show what's there
+    let mut table = Table::new();
+    let format = prettytable::format::FormatBuilder::new()
+        .padding(0, 2)
+        .build();
+    table.set_format(format);
+    table.add_row(row!["Id", "Trigger", "Command"]);
+    for hook in Hook::for_counter(conn.get(), &counter.name)? {
+        table.add_row(row![hook.id, hook.trigger, hook.command]);
+    }
+    table.print(out)?;
+    Ok(())
+}
+
+pub(crate) fn set(conn: &Connection, counter: &mut Counter, args: SetArgs) -> Result<()> {
+    let before = counter.count;
+    if let Some(version) = args.semver {
+        counter.kind = Kind::Semver(version);
+    }
+    if let Some(scheme) = args.calver {
+        counter.kind = match &counter.kind {
+            Kind::Calver(version) => Kind::Calver(Calver {
+                scheme,
+                date: version.date,
+            }),
+            _ => Kind::Calver(Calver {
+                scheme,
+                date: clock::today()?,
+            }),
+        };
+    }
+    if args.gauge && !matches!(counter.kind, Kind::Gauge(_)) {
+        // Statistics start from the value the gauge is set to
+        let value = args.count.unwrap_or(counter.count);
+        counter.kind = Kind::Gauge(Gauge::new(value));
+        counter.count = value;
+    } else if let Some(count) = args.count {
+        if let Kind::Semver(_) = counter.kind {
+            bail!(
+                "counter '{}' holds a version, set it with --semver",
+                counter.name
+            );
+        }
+        if let Kind::Formula(_) = counter.kind {
+            bail!(
+                "counter '{}' is a formula, change it with 'tally {} define'",
+                counter.name,
+                counter.name
+            );
+        }
+        counter.set_count(count);
+    }
+    if let Some(step) = args.step {
+        counter.step = step;
+    }
+    if let Some(template) = args.template {
+        counter.template = template
+    }
+    if args.default {
+        counter.set_default(conn.get())?;
+    }
+    if args.count.is_some() {
+        save(conn, counter, before)
+    } else {
+        Ok(counter.update(conn.get())?)
+    }
+}
+
+fn bump(counter: &mut Counter, args: &BumpArgs) -> Result<()> {
+    match &mut counter.kind {
+        Kind::Semver(version) => version.bump(args.part, args.id.as_deref()),
+        Kind::Calver(_) => bail!(
+            "counter '{}' is a calendar version, release with 'tally {} add'",
+            counter.name,
+            counter.name
+        ),
+        Kind::Counter | Kind::Gauge(_) | Kind::Formula(_) => bail!(
+            "counter '{}' isn't a version, make it one with 'tally {} set --semver 0.1.0'",
+            counter.name,
+            counter.name
+        ),
+    }
+}
+
+fn list(config: &Config, conn: &Connection, args: ListArgs, out: &mut dyn Write) -> Result<()> {
+    let rows = Counter::get_all(conn.get())?;
+    let default = Counter::get_default(conn.get())?.unwrap_or_default();
+
+    let format = args.format.or(config.format).unwrap_or_default();
+    if format == Format::Json {
+        let rows: Vec<_> = rows
+            .iter()
+            .map(|row| counter_json(conn, row, &default))
+            .collect();
+        writeln!(out, "{}", serde_json::to_string_pretty(&rows)?)?;
+        return Ok(());
+    }
+
+    // Create and format table
+    let mut table = Table::new();
+    let format = prettytable::format::FormatBuilder::new()
+        .padding(0, 2)
+        .build();
+    table.set_format(format);
+    let has_headers = !args.no_headers;
+    if has_headers {
+        table.add_row(row!["Name", "Count", "Step", "Template", "Default"]);
+    }
+
+    // Add rows of data to table
+    for row in rows.iter() {
+        let is_default = if default == row.name { "*" } else { "" };
+        // A formula is marked, and shows '?' when it can't be worked out
+        let value = template::display(conn, row).unwrap_or_else(|_| "?".to_string());
+        let value = match row.kind {
+            Kind::Formula(_) => format!("= {value}"),
+            _ => value,
+        };
+        table.add_row(row![row.name, value, row.step, row.template, is_default]);
+    }
+
+    let mut rendered = Vec::new();
+    table.print(&mut rendered)?;
+    let rendered = String::from_utf8(rendered)?;
+    let colored = config.color.enabled(std::io::stdout().is_terminal());
+    match rendered.split_once('\n') {
+        Some((header, body)) if has_headers && colored => {
+            write!(out, "\x1b[1m{header}\x1b[0m\n{body}")?
+        }
+        _ => write!(out, "{rendered}")?,
+    }
+    Ok(())
+}
+
+/// A counter as `list --format json` shows it, `default` naming the
+/// default counter.
+pub(crate) fn counter_json(
+    conn: &Connection,
+    counter: &Counter,
+    default: &str,
+) -> serde_json::Value {
+    json!({
+        "name": counter.name,
+        "count": counter.count,
+        "kind": counter.kind.name(),
+        "value": template::display(conn, counter).ok(),
+        "formula": match &counter.kind {
+            Kind::Formula(formula) => Some(formula.to_string()),
+            _ => None,
+        },
+        "step": counter.step,
+        "template": counter.template,
+        "default": default == counter.name,
+    })
+}
+
+fn stats(
+    config: &Config,
+    conn: &Connection,
+    counter: &Counter,
+    args: StatsArgs,
+    out: &mut dyn Write,
+) -> Result<()> {
+    let now = clock::now()?.timestamp();
+    let since = args
+        .since
+        .map_or(i64::MIN, |span| now.saturating_sub(span.seconds));
+    let entries = history::load(conn.get(), &counter.name, counter.scope.as_deref(), since)?;
+    // Without --since the window opens at the first change
+    let from = match args.since {
+        Some(_) => since,
+        None => entries.first().map_or(now, |e| e.timestamp),
+    };
+    let summary = history::summarize(&entries, from, now, args.bucket)?;
+    let gauge = match &counter.kind {
+        Kind::Gauge(gauge) => Some(gauge),
+        _ => None,
+    };
+
+    let format = args.format.or(config.format).unwrap_or_default();
+    if format == Format::Json {
+        let mut stats = json!({ "name": counter.name });
+        if let Some(gauge) = gauge {
+            stats["last"] = json!(counter.count);
+            stats["min"] = json!(gauge.min);
+            stats["max"] = json!(gauge.max);
+            stats["avg"] = json!(gauge.avg());
+            stats["samples"] = json!(gauge.samples);
+        }
+        let [p50, p90, p99] = summary.percentiles.map_or([None; 3], |p| p.map(Some));
+        stats["changes"] = json!(summary.changes);
+        stats["total"] = wide(summary.total);
+        stats["mean"] = json!(summary.mean);
+        stats["p50"] = json!(p50);
+        stats["p90"] = json!(p90);
+        stats["p99"] = json!(p99);
+        stats["rate_per_day"] = json!(summary.rate_per_day);
+        if args.bucket.is_some() {
+            let buckets: Vec<_> = summary
+                .buckets
+                .iter()
+                .map(|b| json!({ "start": b.label(), "sum": wide(b.sum), "changes": b.changes }))
+                .collect();
+            stats["buckets"] = json!(buckets);
+        }
+        writeln!(out, "{}", serde_json::to_string_pretty(&stats)?)?;
+        return Ok(());
+    }
+
+    let table_format = prettytable::format::FormatBuilder::new()
+        .padding(0, 2)
+        .build();
+    let mut table = Table::new();
+    table.set_format(table_format);
+    if let Some(gauge) = gauge {
+        table.add_row(row!["Last", counter.count]);
+        table.add_row(row!["Min", gauge.min]);
+        table.add_row(row!["Max", gauge.max]);
+        table.add_row(row!["Avg", format!("{:.2}", gauge.avg())]);
+        table.add_row(row!["Samples", gauge.samples]);
+    }
+    let percentile = |i: usize| {
+        summary
+            .percentiles
+            .map_or("-".to_string(), |p| p[i].to_string())
+    };
+    table.add_row(row!["Changes", summary.changes]);
+    table.add_row(row!["Total", summary.total]);
+    table.add_row(row![
+        "Mean",
+        summary.mean.map_or("-".to_string(), |m| format!("{m:.2}"))
+    ]);
+    table.add_row(row!["P50", percentile(0)]);
+    table.add_row(row!["P90", percentile(1)]);
+    table.add_row(row!["P99", percentile(2)]);
+    table.add_row(row!["Rate", format!("{:.2}/day", summary.rate_per_day)]);
+    table.print(out)?;
+
+    if args.bucket.is_some() {
+        let mut buckets = Table::new();
+        buckets.set_format(table_format);
+        buckets.add_row(row!["Bucket", "Sum", "Changes"]);
+        for bucket in &summary.buckets {
+            buckets.add_row(row![bucket.label(), bucket.sum, bucket.changes]);
+        }
+        writeln!(out)?;
+        buckets.print(out)?;
+    }
+    Ok(())
+}
+
+/// A sum of counts in JSON, as a float once it's too big for an integer.
+fn wide(n: i128) -> serde_json::Value {
+    serde_json::Number::from_i128(n).map_or_else(|| json!(n as f64), serde_json::Value::Number)
+}
+
+fn chart(conn: &Connection, counter: &Counter, args: ChartArgs, out: &mut dyn Write) -> Result<()> {
+    let now = clock::now()?.timestamp();
+    let since = args
+        .since
+        .map_or(i64::MIN, |span| now.saturating_sub(span.seconds));
+    let entries = history::load(conn.get(), &counter.name, counter.scope.as_deref(), since)?;
+    let from = match args.since {
+        Some(_) => since,
+        None => entries.first().map_or(now, |e| e.timestamp),
+    };
+    let points = history::series(&entries, counter.count, from, now, args.bucket)?;
+    let values: Vec<i64> = points.iter().map(|(_, value)| *value).collect();
+    if args.spark {
+        writeln!(out, "{}", history::sparkline(&values))?;
+        return Ok(());
+    }
+
+    // Bars start from zero unless the counter went below it
+    let min = values.iter().copied().min().unwrap_or(0).min(0);
+    let max = values.iter().copied().max().unwrap_or(0).max(0);
+    for (bucket, value) in &points {
+        let bar = history::bar(*value, min, max, args.width);
+        writeln!(
+            out,
+            "{}  {bar:<width$}  {value}",
+            bucket.label(),
+            width = args.width
+        )?;
+    }
+    Ok(())
+}
+
+fn watch(database_path: &Path, args: &WatchArgs, out: &mut dyn Write) -> Result<()> {
+    let interval = Duration::try_from_secs_f64(args.interval)
+        .ok()
+        .filter(|interval| !interval.is_zero())
+        .ok_or_else(|| anyhow!("--interval must be a positive number of seconds"))?;
+    let conn = Connection::open_shared(&database_path.to_string_lossy())?;
+    let json = args.format == Some(Format::Json);
+
+    let mut seen: BTreeMap<String, String> = BTreeMap::new();
+    let mut version = None;
+    loop {
+        // Only look at the counters once something has been committed
+        let current = conn.data_version()?;
+        if version != Some(current) {
+            version = Some(current);
+            let mut values = BTreeMap::new();
+            for counter in Counter::get_all(conn.get())? {
+                if args.names.is_empty() || args.names.contains(&counter.name) {
+                    let value =
+                        template::display(&conn, &counter).unwrap_or_else(|_| "?".to_string());
+                    values.insert(counter.name, value);
+                }
+            }
+            for (name, value) in &values {
+                if seen.get(name) != Some(value) {
+                    watch_event(out, json, name, Some(value))?;
+                }
+            }
+            for name in seen.keys().filter(|name| !values.contains_key(*name)) {
+                watch_event(out, json, name, None)?;
+            }
+            out.flush()?;
+            seen = values;
+        }
+        sleep(interval);
+    }
+}
+
+/// One line saying a counter changed, `value` is `None` once it's deleted.
+fn watch_event(out: &mut dyn Write, json: bool, name: &str, value: Option<&str>) -> Result<()> {
+    if json {
+        let event = json!({ "name": name, "value": value, "deleted": value.is_none() });
+        writeln!(out, "{event}")?;
+    } else {
+        writeln!(out, "{name} {}", value.unwrap_or("deleted"))?;
+    }
+    Ok(())
+}
+
+fn render(
+    conn: &Connection,
+    name: Option<&str>,
+    scope: Option<&str>,
+    args: &RenderArgs,
+    out: &mut dyn Write,
+) -> Result<()> {
+    let counter = match name {
+        Some(name) => {
+            let counter = Counter::get(conn.get(), name)?
+                .ok_or_else(|| anyhow!("counter '{name}' does not exist"))?;
+            match scope {
+                Some(scope) => Some(counter.with_scope(conn.get(), scope)?),
+                None => Some(counter),
+            }
+        }
+        None => None,
+    };
+    let rendered = template::render_template(conn, &args.template, counter.as_ref())
+        .with_context(|| format!("failed to render '{}'", args.template))?;
+    writeln!(out, "{rendered}")?;
+    Ok(())
+}
+
+fn check_template(conn: &Connection, name: &str, quiet: bool, out: &mut dyn Write) -> Result<()> {
+    if Counter::get(conn.get(), name)?.is_none() {
+        bail!("counter '{name}' does not exist");
+    }
+    let graph = template::Graph::new(&Counter::get_all(conn.get())?);
+    let problems = graph.check(name);
+    if !problems.is_empty() {
+        return Err(problems_error(&problems));
+    }
+    if !quiet {
+        writeln!(out, "template of counter '{name}' is ok")?;
+    }
+    Ok(())
+}
+
+fn graph(conn: &Connection, args: &GraphArgs, out: &mut dyn Write) -> Result<()> {
+    let graph = template::Graph::new(&Counter::get_all(conn.get())?);
+    if args.dot {
+        write!(out, "{}", graph.dot())?;
+    } else {
+        write!(out, "{}", graph.text())?;
+    }
+    let problems = graph.check_all();
+    if !problems.is_empty() {
+        return Err(problems_error(&problems));
+    }
+    Ok(())
+}
+
+/// One error listing every problem found in the templates.
+fn problems_error(problems: &[template::Problem]) -> anyhow::Error {
+    let lines: Vec<_> = problems.iter().map(|p| format!("  {p}")).collect();
+    anyhow!(
+        "found {} template problem(s):\n{}",
+        problems.len(),
+        lines.join("\n")
+    )
+}
+
+fn nuke(database_path: &Path, args: NukeArgs, out: &mut dyn Write) -> Result<()> {
+    let confirmed = if args.yes {
+        true
+    } else {
+        write!(out, "Are you sure wish to nuke? (y/n): ")?;
+        out.flush()?;
+
+        let mut input = String::new();
+        std::io::stdin().read_line(&mut input)?;
+
+        input.to_lowercase().trim() == "y"
+    };
+
+    if confirmed {
+        std::fs::remove_file(database_path)?;
+        writeln!(out, "Database deleted successfully.")?;
+    }
+    Ok(())
+}
+
+fn completions(shell: Shell, out: &mut dyn Write) -> Result<()> {
+    let name = shell.to_possible_value().unwrap();
+    let shells = Shells::builtins();
+    let completer = shells
+        .completer(name.get_name())
+        .ok_or_else(|| anyhow!("unsupported shell '{}'", name.get_name()))?;
+    completer.write_registration("COMPLETE", "tally", "tally", "tally", out)?;
+    Ok(())
+}
+
+fn man(settings: &Settings, args: &ManArgs, out: &mut dyn Write) -> Result<()> {
+    let cmd = settings.command();
+    match &args.out_dir {
+        Some(out_dir) => {
+            std::fs::create_dir_all(out_dir)?;
+            clap_mangen::generate_to(cmd, out_dir)?;
+        }
+        None => clap_mangen::Man::new(cmd).render(out)?,
+    }
+    Ok(())
+}
@@ -0,0 +1,338 @@
+use crate::config::{Color, Format};
+use crate::database;
+use crate::git::Scope;
+use crate::history::Span;
+use crate::models::Counter;
+use crate::template::Formula;
+use crate::version::{Part, Scheme, Semver};
+use clap::{ArgGroup, Args, CommandFactory, Parser, Subcommand, ValueEnum, ValueHint};
+use clap_complete::{ArgValueCandidates, CompletionCandidate};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Parser)]
+#[command(name = "tally", version, about = "A global counter")]
+pub struct Cli {
+    /// Name of counter
+    pub name: Option<String>,
+
+    /// Render counter without template (if template is set)
+    #[arg(long)]
+    pub raw: bool,
+
+    /// Add to counter but don't write to stdout.
+    #[arg(long, short)]
+    pub quiet: bool,
+
+    /// Path to the counter database
+    #[arg(long, env = "TALLY_DATABASE", global = true, value_hint = ValueHint::FilePath)]
+    pub database: Option<PathBuf>,
+
+    /// Fail instead of creating counters that don't exist
+    #[arg(long, global = true)]
+    pub strict: bool,
+
+    /// Keep a separate value of the counter per git repository or branch
+    #[arg(long, global = true)]
+    pub scope: Option<Scope>,
+
+    #[command(subcommand)]
+    pub command: Option<Commands>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Commands {
+    /// Set configuration of the provided counter
+    Set(SetArgs),
+    /// Increment a given counter
+    Add(AmountArgs),
+    /// Decrement a given counter
+    Sub(AmountArgs),
+    /// Bump a version counter, resetting the parts below
+    Bump(BumpArgs),
+    /// Make the counter a formula over other counters, worked out when read
+    Define(DefineArgs),
+    /// Run a command when the count crosses a threshold, or list the hooks
+    On(OnArgs),
+    /// Delete a given counter
+    Delete,
+    /// List all of the active counters
+    List(ListArgs),
+    /// Show statistics of the counter's changes over time
+    Stats(StatsArgs),
+    /// Chart the counter's count over time
+    Chart(ChartArgs),
+    /// Print counters as they change
+    Watch(WatchArgs),
+    /// Browse and change the counters in an interactive dashboard
+    Top,
+    /// Keep the database open and answer requests over a socket or HTTP
+    Serve(ServeArgs),
+    /// Nuke the counter database
+    Nuke(NukeArgs),
+    /// Render a one-off template without storing it
+    Render(RenderArgs),
+    /// Check the counter's template and the ones it references
+    CheckTemplate,
+    /// Print which counters each template reads
+    Graph(GraphArgs),
+    /// Print the shell completion script
+    Completions(CompletionsArgs),
+    /// Print the man page, or write every page to a directory
+    Man(ManArgs),
+}
+
+#[derive(Debug, Default, Args)]
+pub struct SetArgs {
+    /// Set the count to this integer
+    #[arg(allow_negative_numbers = true)]
+    pub count: Option<i64>,
+
+    /// Default increment amount for given counter
+    #[arg(long, short, allow_negative_numbers = true)]
+    pub step: Option<i64>,
+
+    /// Template to render when counter is invoked
+    #[arg(long, short)]
+    pub template: Option<String>,
+
+    /// Provided counter becomes the default
+    #[arg(long)]
+    pub default: bool,
+
+    /// Make this a semantic version counter holding the version given
+    #[arg(long, value_name = "VERSION", conflicts_with = "count")]
+    pub semver: Option<Semver>,
+
+    /// Make this a calendar version counter written as SCHEME, such as YYYY.0M.N
+    #[arg(long, value_name = "SCHEME", conflicts_with = "semver")]
+    pub calver: Option<Scheme>,
+
+    /// Make this a gauge, which is set rather than added to and keeps statistics
+    #[arg(long, conflicts_with_all = ["semver", "calver"])]
+    pub gauge: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct AmountArgs {
+    /// Amount to change the counter by, defaults to its step
+    #[arg(allow_negative_numbers = true)]
+    pub amount: Option<i64>,
+}
+
+#[derive(Debug, Args)]
+pub struct BumpArgs {
+    /// Part of the version to bump
+    pub part: Part,
+
+    /// Pre-release identifier when bumping pre, such as rc or beta
+    #[arg(long)]
+    pub id: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct DefineArgs {
+    /// Expression over other counters, such as "{passed} / {total}"
+    pub formula: Formula,
+}
+
+#[derive(Debug, Args)]
+#[command(group = ArgGroup::new("trigger").args(["above", "below", "equals", "every"]))]
+pub struct OnArgs {
+    /// Run when the count goes over this value
+    #[arg(long, allow_negative_numbers = true, requires = "run")]
+    pub above: Option<i64>,
+
+    /// Run when the count goes under this value
+    #[arg(long, allow_negative_numbers = true, requires = "run")]
+    pub below: Option<i64>,
+
+    /// Run when the count lands on this value
+    #[arg(long, allow_negative_numbers = true, requires = "run")]
+    pub equals: Option<i64>,
+
+    /// Run each time the count passes a multiple of this value
+    #[arg(long, requires = "run")]
+    pub every: Option<i64>,
+
+    /// Shell command to run, given TALLY_COUNTER, TALLY_VALUE and TALLY_PREVIOUS
+    #[arg(long, requires = "trigger")]
+    pub run: Option<String>,
+
+    /// Remove the hook with this id
+    #[arg(long, value_name = "ID", conflicts_with_all = ["trigger", "run"])]
+    pub remove: Option<i64>,
+}
+
+#[derive(Debug, Args)]
+pub struct ListArgs {
+    /// List counters without column headers
+    #[arg(long)]
+    pub no_headers: bool,
+
+    /// Output format of the listing
+    #[arg(long, short)]
+    pub format: Option<Format>,
+}
+
+#[derive(Debug, Args)]
+pub struct StatsArgs {
+    /// Only count changes this recent, such as 12h, 7d or 4w
+    #[arg(long, value_name = "DURATION")]
+    pub since: Option<Span>,
+
+    /// Sum the changes in buckets of this length, such as 1h or 1d
+    #[arg(long, value_name = "DURATION")]
+    pub bucket: Option<Span>,
+
+    /// Output format of the statistics
+    #[arg(long, short)]
+    pub format: Option<Format>,
+}
+
+#[derive(Debug, Args)]
+pub struct ChartArgs {
+    /// Only chart this far back, such as 12h, 7d or 4w
+    #[arg(long, value_name = "DURATION")]
+    pub since: Option<Span>,
+
+    /// Length of time each bar or point stands for
+    #[arg(long, value_name = "DURATION", default_value = "1d")]
+    pub bucket: Span,
+
+    /// Draw a one line sparkline instead of bars
+    #[arg(long)]
+    pub spark: bool,
+
+    /// Length of the longest bar
+    #[arg(long, default_value_t = 40)]
+    pub width: usize,
+}
+
+#[derive(Debug, Args)]
+pub struct WatchArgs {
+    /// Counters to watch, all of them if none are given
+    pub names: Vec<String>,
+
+    /// Seconds between checks for changes
+    #[arg(long, short, default_value_t = 1.0)]
+    pub interval: f64,
+
+    /// Output format of the changes, json prints an object per line
+    #[arg(long, short)]
+    pub format: Option<Format>,
+}
+
+#[derive(Debug, Args)]
+pub struct ServeArgs {
+    /// Unix socket to listen on, next to the database by default
+    #[arg(long, value_name = "PATH", value_hint = ValueHint::FilePath, conflicts_with = "http")]
+    pub socket: Option<PathBuf>,
+
+    /// Serve a JSON API over HTTP on this address instead, such as 127.0.0.1:8080
+    #[arg(long, value_name = "ADDRESS")]
+    pub http: Option<String>,
+
+    /// Bearer token HTTP clients must send in their Authorization header
+    #[arg(long, env = "TALLY_TOKEN", hide_env_values = true)]
+    pub token: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct NukeArgs {
+    /// Skip the confirmation prompt
+    #[arg(long, short)]
+    pub yes: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct RenderArgs {
+    /// Template to render, `{}` refers to the named counter if there is one
+    pub template: String,
+}
+
+#[derive(Debug, Args)]
+pub struct GraphArgs {
+    /// Print the graph in Graphviz DOT format
+    #[arg(long)]
+    pub dot: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct CompletionsArgs {
+    /// Shell to generate the script for
+    pub shell: Shell,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+    Elvish,
+    Powershell,
+}
+
+#[derive(Debug, Args)]
+pub struct ManArgs {
+    /// Write tally.1 and a page per subcommand into this directory
+    #[arg(long, short, value_hint = ValueHint::DirPath)]
+    pub out_dir: Option<PathBuf>,
+}
+
+/// Build the command line parser, `database_path` is where shell completion
+/// looks up counter names.
+pub fn command(color: Color, database_path: PathBuf) -> clap::Command {
+    Cli::command().color(color.into()).mut_arg("name", |arg| {
+        arg.add(ArgValueCandidates::new(move || {
+            counter_names(&database_path)
+        }))
+    })
+}
+
+/// Counter names offered when completing the `name` argument. Completion
+/// must never block or create a database, so errors just yield nothing.
+fn counter_names(database_path: &Path) -> Vec<CompletionCandidate> {
+    let Ok(conn) = database::open_read_only(&database_path.to_string_lossy()) else {
+        return Vec::new();
+    };
+    Counter::get_all(&conn)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|c| CompletionCandidate::new(c.name).help(Some(c.count.to_string().into())))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn command_is_well_formed() {
+        command(Color::Never, PathBuf::from("tally.db")).debug_assert();
+    }
+
+    #[test]
+    fn name_comes_before_subcommand() {
+        let cli = Cli::try_parse_from(["tally", "foo", "add", "3"]).unwrap();
+        assert_eq!(cli.name.as_deref(), Some("foo"));
+        assert!(matches!(
+            cli.command,
+            Some(Commands::Add(AmountArgs { amount: Some(3) }))
+        ));
+    }
+
+    #[test]
+    fn negative_amounts_are_values() {
+        let cli = Cli::try_parse_from(["tally", "foo", "set", "-4", "--step", "-2"]).unwrap();
+        let Some(Commands::Set(set)) = cli.command else {
+            panic!("expected set");
+        };
+        assert_eq!(set.count, Some(-4));
+        assert_eq!(set.step, Some(-2));
+    }
+
+    #[test]
+    fn non_numeric_amount_is_rejected() {
+        assert!(Cli::try_parse_from(["tally", "foo", "add", "lots"]).is_err());
+    }
+}
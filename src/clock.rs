@@ -0,0 +1,23 @@
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, FixedOffset, Local, NaiveDate};
+
+/// The current time for templates and calendar versions. `SOURCE_DATE_EPOCH`
+/// pins it, as in reproducible builds, and is read as UTC.
+pub fn now() -> Result<DateTime<FixedOffset>> {
+    match std::env::var("SOURCE_DATE_EPOCH") {
+        Ok(epoch) => {
+            let seconds = epoch
+                .trim()
+                .parse()
+                .with_context(|| format!("invalid SOURCE_DATE_EPOCH '{epoch}'"))?;
+            let time = DateTime::from_timestamp(seconds, 0)
+                .ok_or_else(|| anyhow!("SOURCE_DATE_EPOCH '{epoch}' is out of range"))?;
+            Ok(time.fixed_offset())
+        }
+        Err(_) => Ok(Local::now().fixed_offset()),
+    }
+}
+
+pub fn today() -> Result<NaiveDate> {
+    Ok(now()?.date_naive())
+}
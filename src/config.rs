@@ -0,0 +1,166 @@
+use crate::template;
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use dirs::home_dir;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+pub const CONFIG_FILE: &str = "config.toml";
+
+/// User preferences read from `~/.tally/config.toml`.
+///
+/// Every field is optional; anything left out falls back to the built-in
+/// behaviour, and command line flags always win over the file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Path to the SQLite database, `~` is expanded.
+    pub database: Option<PathBuf>,
+    /// Output format used by `list` when `--format` isn't given.
+    pub format: Option<Format>,
+    /// Seconds to wait for another instance to release the database lock.
+    pub lock_timeout: Option<u64>,
+    /// Step given to newly created counters.
+    pub default_step: Option<i64>,
+    /// Template given to newly created counters.
+    pub default_template: Option<String>,
+    /// Refuse to implicitly create counters that don't exist yet.
+    pub strict: bool,
+    /// Whether to colour help and table output.
+    pub color: Color,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    #[default]
+    Table,
+    Json,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Color {
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl Color {
+    /// Whether to colour output going to a stream, `is_terminal` decides
+    /// the `auto` case.
+    pub fn enabled(self, is_terminal: bool) -> bool {
+        match self {
+            Color::Auto => is_terminal,
+            Color::Always => true,
+            Color::Never => false,
+        }
+    }
+}
+
+impl From<Color> for clap::ColorChoice {
+    fn from(color: Color) -> clap::ColorChoice {
+        match color {
+            Color::Auto => clap::ColorChoice::Auto,
+            Color::Always => clap::ColorChoice::Always,
+            Color::Never => clap::ColorChoice::Never,
+        }
+    }
+}
+
+impl Config {
+    /// Load the config at `path`, a missing file yields the defaults.
+    pub fn load(path: &Path) -> Result<Config> {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Config::default()),
+            Err(e) => return Err(e).context(format!("failed to read {}", path.display())),
+        };
+        Config::parse(&contents).context(format!("failed to parse {}", path.display()))
+    }
+
+    pub fn parse(contents: &str) -> Result<Config> {
+        let mut config: Config = toml::from_str(contents)?;
+        config.database = config.database.map(|p| expand_tilde(&p));
+        if let Some(template) = &config.default_template {
+            template::validate(template)
+                .with_context(|| format!("invalid default_template '{template}'"))?;
+        }
+        Ok(config)
+    }
+}
+
+/// Replace a leading `~` with the user's home directory.
+pub fn expand_tilde(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), home_dir()) {
+        (Ok(rest), Some(home)) => home.join(rest),
+        _ => path.to_path_buf(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn empty_file_is_default() {
+        let config = Config::parse("").unwrap();
+        assert!(config.database.is_none());
+        assert!(config.default_step.is_none());
+        assert!(!config.strict);
+        assert_eq!(config.color, Color::Auto);
+    }
+
+    #[test]
+    fn parses_every_key() {
+        let config = Config::parse(
+            r#"
+            database = "/tmp/tally.db"
+            format = "json"
+            lock_timeout = 3
+            default_step = 5
+            default_template = "v{}"
+            strict = true
+            color = "never"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.database, Some(PathBuf::from("/tmp/tally.db")));
+        assert_eq!(config.format, Some(Format::Json));
+        assert_eq!(config.lock_timeout, Some(3));
+        assert_eq!(config.default_step, Some(5));
+        assert_eq!(config.default_template.as_deref(), Some("v{}"));
+        assert!(config.strict);
+        assert_eq!(config.color, Color::Never);
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        assert!(Config::parse("stepp = 2").is_err());
+    }
+
+    #[test]
+    fn invalid_default_template_is_rejected() {
+        let err = Config::parse("default_template = \"{\"").unwrap_err();
+        assert!(format!("{err:#}").contains("default_template"), "{err:#}");
+    }
+
+    #[test]
+    fn missing_file_is_default() {
+        let dir = TempDir::new().unwrap();
+        let config = Config::load(&dir.path().join("nope.toml")).unwrap();
+        assert!(config.format.is_none());
+    }
+
+    #[test]
+    fn tilde_is_expanded() {
+        let home = home_dir().unwrap();
+        assert_eq!(
+            expand_tilde(Path::new("~/x/tally.db")),
+            home.join("x/tally.db")
+        );
+        assert_eq!(expand_tilde(Path::new("/abs")), PathBuf::from("/abs"));
+    }
+}
@@ -0,0 +1,433 @@
+use crate::app;
+use crate::cli::{AmountArgs, Cli, Commands, SetArgs};
+use crate::config::Config;
+use crate::database::Connection;
+use crate::hooks::Hook;
+use crate::models::{Counter, Kind};
+use crate::template;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// What a request asks of a counter, the `op` field of its JSON.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum Op {
+    Read,
+    Add { amount: Option<i64> },
+    Sub { amount: Option<i64> },
+    Set { count: i64 },
+}
+
+/// One line sent to the daemon, such as
+/// `{"op": "add", "name": "builds", "amount": 2}`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Request {
+    #[serde(flatten)]
+    pub op: Op,
+    /// Counter to act on, the default counter if missing
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Key of the scope the counter is kept in, see `git::Repo::key`
+    #[serde(default)]
+    pub scope: Option<String>,
+    #[serde(default)]
+    pub raw: bool,
+    #[serde(default)]
+    pub strict: bool,
+}
+
+/// The line sent back, holding the output of the command or why it failed.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Reply {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Nothing was done, since the answer could depend on the client's own
+    /// environment, so the client should run the command itself
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub local: bool,
+}
+
+impl Request {
+    /// The request to send for an invocation, if the daemon can answer it.
+    pub fn from_cli(cli: &Cli, scope: Option<&str>, strict: bool) -> Option<Request> {
+        // Only this process can honour a pinned clock
+        if std::env::var_os("SOURCE_DATE_EPOCH").is_some() {
+            return None;
+        }
+        let op = match &cli.command {
+            None => Op::Read,
+            Some(Commands::Add(args)) => Op::Add {
+                amount: args.amount,
+            },
+            Some(Commands::Sub(args)) => Op::Sub {
+                amount: args.amount,
+            },
+            Some(Commands::Set(SetArgs {
+                count: Some(count),
+                step: None,
+                template: None,
+                default: false,
+                semver: None,
+                calver: None,
+                gauge: false,
+            })) => Op::Set { count: *count },
+            _ => return None,
+        };
+        Some(Request {
+            op,
+            name: cli.name.clone(),
+            scope: scope.map(str::to_string),
+            raw: cli.raw,
+            strict,
+        })
+    }
+
+    /// The invocation the request stands for.
+    fn cli(self) -> Cli {
+        let command = match self.op {
+            Op::Read => None,
+            Op::Add { amount } => Some(Commands::Add(AmountArgs { amount })),
+            Op::Sub { amount } => Some(Commands::Sub(AmountArgs { amount })),
+            Op::Set { count } => Some(Commands::Set(SetArgs {
+                count: Some(count),
+                ..SetArgs::default()
+            })),
+        };
+        Cli {
+            name: self.name,
+            raw: self.raw,
+            quiet: false,
+            database: None,
+            strict: self.strict,
+            scope: None,
+            command,
+        }
+    }
+}
+
+/// Where the daemon of a database listens unless told otherwise.
+pub fn socket_path(database_path: &Path) -> PathBuf {
+    database_path.with_extension("sock")
+}
+
+/// Run `f` holding the file lock, which servers otherwise leave to others.
+pub(crate) fn locked<T>(
+    conn: &Connection,
+    lock_timeout: Option<Duration>,
+    f: impl FnOnce() -> Result<T>,
+) -> Result<T> {
+    conn.relock(lock_timeout)?;
+    let result = f();
+    conn.unlock()?;
+    result
+}
+
+/// Answer one request line, taking the file lock only while doing so.
+fn answer(
+    config: &Config,
+    conn: &Connection,
+    database_path: &Path,
+    lock_timeout: Option<Duration>,
+    line: &str,
+) -> Result<Reply> {
+    let request: Request =
+        serde_json::from_str(line).with_context(|| format!("invalid request '{line}'"))?;
+    let scope = request.scope.clone();
+
+    let mut out = Vec::new();
+    let answered = locked(conn, lock_timeout, || {
+        if local(conn, &request)? {
+            return Ok(false);
+        }
+        app::counter_command(
+            config,
+            conn,
+            database_path,
+            request.cli(),
+            scope.as_deref(),
+            &mut out,
+        )?;
+        Ok(true)
+    })?;
+    if !answered {
+        return Ok(Reply {
+            local: true,
+            ..Reply::default()
+        });
+    }
+
+    let out = String::from_utf8(out)?;
+    let value = out.strip_suffix('\n').unwrap_or(&out);
+    Ok(Reply {
+        value: (!value.is_empty()).then(|| value.to_string()),
+        ..Reply::default()
+    })
+}
+
+/// Whether the client should answer the request itself, because the
+/// outcome could depend on its environment, working directory or clock:
+/// the counter renders `{env:...}`, `{date}` or `{now}`, is a calendar
+/// version, runs hooks, or doesn't exist yet and takes the client's
+/// defaults.
+fn local(conn: &Connection, request: &Request) -> Result<bool> {
+    let name = match &request.name {
+        Some(name) => name.clone(),
+        None => match Counter::get_default(conn.get())? {
+            Some(name) => name,
+            None => return Ok(true),
+        },
+    };
+    let Some(mut counter) = Counter::get(conn.get(), &name)? else {
+        return Ok(true);
+    };
+    if let Some(scope) = &request.scope {
+        counter = counter.with_scope(conn.get(), scope)?;
+    }
+    Ok(matches!(counter.kind, Kind::Calver(_))
+        || (request.op != Op::Read && !Hook::for_counter(conn.get(), &name)?.is_empty())
+        || (!request.raw && template::uses_environment(conn, &counter)?))
+}
+
+fn reply(result: Result<Reply>) -> Reply {
+    result.unwrap_or_else(|e| Reply {
+        error: Some(format!("{e:#}")),
+        ..Reply::default()
+    })
+}
+
+#[cfg(unix)]
+pub use unix::{send, serve};
+
+#[cfg(unix)]
+mod unix {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::fs::FileTypeExt;
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::sync::Mutex;
+    use std::thread;
+
+    /// Keep `conn` open and answer requests on `socket` until killed. The
+    /// lock is let go of in between, so other invocations aren't held up.
+    pub fn serve(
+        config: &Config,
+        conn: Connection,
+        database_path: &Path,
+        lock_timeout: Option<Duration>,
+        socket: &Path,
+    ) -> Result<()> {
+        if let Ok(metadata) = std::fs::symlink_metadata(socket) {
+            if !metadata.file_type().is_socket() {
+                bail!("{} exists and is not a socket", socket.display());
+            }
+            if UnixStream::connect(socket).is_ok() {
+                bail!(
+                    "a tally daemon is already listening on {}",
+                    socket.display()
+                );
+            }
+            // Left behind by a daemon that was killed
+            std::fs::remove_file(socket)?;
+        }
+        let listener = UnixListener::bind(socket)
+            .with_context(|| format!("failed to listen on {}", socket.display()))?;
+        conn.unlock()?;
+
+        // Requests take turns so counts are read and written in one go
+        let conn = Mutex::new(conn);
+        thread::scope(|scope| {
+            for stream in listener.incoming() {
+                // One failed accept shouldn't take down everyone else's
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        eprintln!("tally: {e}");
+                        continue;
+                    }
+                };
+                let conn = &conn;
+                scope.spawn(move || {
+                    if let Err(e) = client(config, conn, database_path, lock_timeout, stream) {
+                        eprintln!("tally: {e:#}");
+                    }
+                });
+            }
+            Ok(())
+        })
+    }
+
+    /// Answer every line a client sends until it hangs up.
+    fn client(
+        config: &Config,
+        conn: &Mutex<Connection>,
+        database_path: &Path,
+        lock_timeout: Option<Duration>,
+        stream: UnixStream,
+    ) -> Result<()> {
+        let mut writer = stream.try_clone()?;
+        for line in BufReader::new(stream).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let result = {
+                let conn = conn.lock().unwrap_or_else(|e| e.into_inner());
+                answer(config, &conn, database_path, lock_timeout, &line)
+            };
+            writeln!(writer, "{}", serde_json::to_string(&reply(result))?)?;
+        }
+        Ok(())
+    }
+
+    /// Have the daemon listening on `socket` answer the request, returning
+    /// `None` when no daemon is running.
+    pub fn send(socket: &Path, request: &Request) -> Result<Option<Option<String>>> {
+        let Ok(mut stream) = UnixStream::connect(socket) else {
+            return Ok(None);
+        };
+        writeln!(stream, "{}", serde_json::to_string(request)?)?;
+        let mut line = String::new();
+        BufReader::new(stream).read_line(&mut line)?;
+        let reply: Reply = serde_json::from_str(&line)
+            .with_context(|| format!("invalid reply from the daemon on {}", socket.display()))?;
+        match reply.error {
+            Some(error) => bail!(error),
+            None if reply.local => Ok(None),
+            None => Ok(Some(reply.value)),
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub fn serve(
+    _config: &Config,
+    _conn: Connection,
+    _database_path: &Path,
+    _lock_timeout: Option<Duration>,
+    _socket: &Path,
+) -> Result<()> {
+    bail!("serving over a socket needs a Unix system")
+}
+
+#[cfg(not(unix))]
+pub fn send(_socket: &Path, _request: &Request) -> Result<Option<Option<String>>> {
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hooks::Trigger;
+    use clap::Parser;
+    use tempfile::TempDir;
+
+    fn request(args: &[&str]) -> Option<Request> {
+        let cli = Cli::try_parse_from([&["tally"], args].concat()).unwrap();
+        Request::from_cli(&cli, None, false)
+    }
+
+    #[test]
+    fn only_simple_commands_are_sent() {
+        let add = request(&["builds", "add", "2"]).unwrap();
+        assert_eq!(add.op, Op::Add { amount: Some(2) });
+        assert_eq!(add.name.as_deref(), Some("builds"));
+        assert_eq!(request(&["--raw"]).unwrap().op, Op::Read);
+        assert_eq!(
+            request(&["builds", "set", "5"]).unwrap().op,
+            Op::Set { count: 5 }
+        );
+        assert!(request(&["builds", "set", "5", "--step", "2"]).is_none());
+        assert!(request(&["builds", "set", "--default"]).is_none());
+        assert!(request(&["list"]).is_none());
+
+        let json = serde_json::to_string(&add).unwrap();
+        assert_eq!(
+            json,
+            r#"{"op":"add","amount":2,"name":"builds","scope":null,"raw":false,"strict":false}"#
+        );
+        let minimal: Request = serde_json::from_str(r#"{"op":"sub"}"#).unwrap();
+        assert_eq!(minimal.op, Op::Sub { amount: None });
+        assert_eq!(minimal.name, None);
+    }
+
+    #[test]
+    fn requests_run_like_the_commands() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("t.db");
+        let conn = Connection::new(&path.to_string_lossy()).unwrap();
+        Counter::new("builds").insert(conn.get()).unwrap();
+        Counter::new("builds").set_default(conn.get()).unwrap();
+        conn.unlock().unwrap();
+        let config = Config::default();
+        let answer = |line: &str| answer(&config, &conn, &path, None, line);
+        let value = |value: &str| Reply {
+            value: Some(value.to_string()),
+            ..Reply::default()
+        };
+
+        assert_eq!(
+            answer(r#"{"op": "add", "name": "builds", "amount": 2}"#).unwrap(),
+            value("2")
+        );
+        assert_eq!(
+            answer(r#"{"op": "set", "name": "builds", "count": 7}"#).unwrap(),
+            Reply::default()
+        );
+        assert_eq!(
+            answer(r#"{"op": "read", "name": "builds"}"#).unwrap(),
+            value("7")
+        );
+        assert_eq!(answer(r#"{"op": "read"}"#).unwrap(), value("7"));
+        assert!(answer("add builds").is_err());
+
+        // The lock is let go of between requests
+        drop(Connection::new(&path.to_string_lossy()).unwrap());
+    }
+
+    #[test]
+    fn environment_dependent_requests_are_left_to_the_client() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("t.db");
+        let conn = Connection::new(&path.to_string_lossy()).unwrap();
+        let mut who = Counter::new("who");
+        who.template = "{env:WHO}{}".to_string();
+        who.insert(conn.get()).unwrap();
+        let mut label = Counter::new("label");
+        label.template = "v{who}".to_string();
+        label.insert(conn.get()).unwrap();
+        let mut release = Counter::new("release");
+        release.kind = Kind::Calver(crate::version::Calver {
+            scheme: "YYYY.N".parse().unwrap(),
+            date: chrono::NaiveDate::from_ymd_opt(2026, 1, 1).unwrap(),
+        });
+        release.insert(conn.get()).unwrap();
+        Counter::new("builds").insert(conn.get()).unwrap();
+        Hook::add(conn.get(), "builds", Trigger::Every(1), "true").unwrap();
+        conn.unlock().unwrap();
+        let config = Config::default();
+        let answer = |line: &str| answer(&config, &conn, &path, None, line).unwrap();
+        let local = Reply {
+            local: true,
+            ..Reply::default()
+        };
+
+        assert_eq!(answer(r#"{"op": "read", "name": "who"}"#), local);
+        assert_eq!(answer(r#"{"op": "read", "name": "label"}"#), local);
+        assert_eq!(answer(r#"{"op": "add", "name": "release"}"#), local);
+        assert_eq!(answer(r#"{"op": "add", "name": "builds"}"#), local);
+        assert_eq!(answer(r#"{"op": "add", "name": "missing"}"#), local);
+        assert_eq!(
+            answer(r#"{"op": "read", "name": "who", "raw": true}"#).value,
+            Some("0".to_string())
+        );
+        assert_eq!(
+            answer(r#"{"op": "read", "name": "builds"}"#).value,
+            Some("0".to_string())
+        );
+        assert_eq!(serde_json::to_string(&local).unwrap(), r#"{"local":true}"#);
+    }
+}
@@ -1,12 +1,114 @@
 use crate::models::Counter;
-use anyhow::Result;
+use anyhow::{bail, Result};
 use fs2::FileExt;
 use sqlite::ConnectionThreadSafe;
+use std::fmt;
 use std::fs::File;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Schema changes since the original tables, applied in order and tracked
+/// with `PRAGMA user_version`.
+const MIGRATIONS: &[&str] = &[
+    // Counter kinds, `state` holds whatever the kind needs beyond `count`
+    "
+    ALTER TABLE counters ADD COLUMN kind TEXT NOT NULL DEFAULT 'counter';
+    ALTER TABLE counters ADD COLUMN state TEXT NOT NULL DEFAULT '';
+    ",
+    // Values of counters kept apart per scope, such as a git branch
+    "
+    CREATE TABLE scoped_counts (
+        name TEXT NOT NULL,
+        scope TEXT NOT NULL,
+        count INTEGER NOT NULL,
+        kind TEXT NOT NULL,
+        state TEXT NOT NULL,
+        PRIMARY KEY (name, scope)
+    );
+    ",
+    // Every change of a count, for statistics over time
+    "
+    CREATE TABLE history (
+        name TEXT NOT NULL,
+        scope TEXT NOT NULL,
+        timestamp INTEGER NOT NULL,
+        count INTEGER NOT NULL,
+        delta INTEGER NOT NULL
+    );
+    CREATE INDEX history_by_time ON history (name, scope, timestamp);
+    ",
+    // Commands run when a count crosses a threshold
+    "
+    CREATE TABLE hooks (
+        id INTEGER PRIMARY KEY,
+        name TEXT NOT NULL,
+        trigger TEXT NOT NULL,
+        value INTEGER NOT NULL,
+        command TEXT NOT NULL
+    );
+    ",
+];
 
 pub struct Connection {
     conn: ConnectionThreadSafe,
-    lock_file: File,
+    lock: Option<Lock>,
+}
+
+/// Gave up waiting for another instance to release the lock.
+#[derive(Debug)]
+pub struct LockTimeout {
+    path: String,
+    waited: Duration,
+}
+
+impl fmt::Display for LockTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "timed out after {}s waiting for {}",
+            self.waited.as_secs_f64(),
+            self.path
+        )
+    }
+}
+
+impl std::error::Error for LockTimeout {}
+
+/// The file held exclusively by whoever is changing the database.
+struct Lock {
+    path: String,
+    file: File,
+}
+
+impl Lock {
+    fn acquire(&self, timeout: Option<Duration>) -> Result<()> {
+        if self.file.try_lock_exclusive().is_ok() {
+            return Ok(());
+        }
+        eprintln!(
+            "tally: waiting for another instance to release {}",
+            self.path
+        );
+        match timeout {
+            None => self.file.lock_exclusive()?,
+            Some(timeout) => {
+                let start = Instant::now();
+                while self.file.try_lock_exclusive().is_err() {
+                    if start.elapsed() >= timeout {
+                        return Err(LockTimeout {
+                            path: self.path.clone(),
+                            waited: timeout,
+                        }
+                        .into());
+                    }
+                    sleep(LOCK_POLL_INTERVAL);
+                }
+            }
+        }
+        Ok(())
+    }
 }
 
 impl Connection {
@@ -15,14 +117,19 @@ impl Connection {
     }
 
     pub fn new(name: &str) -> Result<Connection> {
-        // Acquire file lock
-        let lock_path = format!("{}.lock", name);
-        let lock_file = File::create(&lock_path)?;
+        Connection::open(name, None)
+    }
 
-        if lock_file.try_lock_exclusive().is_err() {
-            eprintln!("tally: waiting for another instance to release {lock_path}");
-            lock_file.lock_exclusive()?;
-        }
+    /// Open the database, giving up on the file lock after `lock_timeout`
+    /// instead of waiting indefinitely.
+    pub fn open(name: &str, lock_timeout: Option<Duration>) -> Result<Connection> {
+        // Acquire file lock
+        let path = format!("{}.lock", name);
+        let lock = Lock {
+            file: File::create(&path)?,
+            path,
+        };
+        lock.acquire(lock_timeout)?;
 
         let mut connection = sqlite::Connection::open_thread_safe(name)?;
         connection.set_busy_timeout(5_000)?;
@@ -30,12 +137,47 @@ impl Connection {
 
         let mut conn = Connection {
             conn: connection,
-            lock_file,
+            lock: Some(lock),
         };
         conn.init_database()?;
         Ok(conn)
     }
 
+    /// Open an existing database without taking the file lock, for long
+    /// running readers that mustn't hold up writers.
+    pub fn open_shared(name: &str) -> Result<Connection> {
+        let mut connection = sqlite::Connection::open_thread_safe(name)?;
+        connection.set_busy_timeout(5_000)?;
+        Ok(Connection {
+            conn: connection,
+            lock: None,
+        })
+    }
+
+    /// Let go of the file lock until `relock`, so a long running process
+    /// can leave the database to others between its changes.
+    pub fn unlock(&self) -> Result<()> {
+        if let Some(lock) = &self.lock {
+            FileExt::unlock(&lock.file)?;
+        }
+        Ok(())
+    }
+
+    /// Take the file lock back after `unlock`.
+    pub fn relock(&self, lock_timeout: Option<Duration>) -> Result<()> {
+        match &self.lock {
+            Some(lock) => lock.acquire(lock_timeout),
+            None => bail!("the database was opened without a lock"),
+        }
+    }
+
+    /// Number that changes whenever another connection commits.
+    pub fn data_version(&self) -> Result<i64> {
+        let mut stmt = self.conn.prepare("PRAGMA data_version;")?;
+        stmt.next()?;
+        Ok(stmt.read::<i64, _>(0)?)
+    }
+
     fn init_database(&mut self) -> Result<()> {
         // create the default table
         self.conn.execute(
@@ -59,6 +201,8 @@ impl Connection {
             ",
         )?;
 
+        self.migrate()?;
+
         // Setup default counter
         let mut stmt = self.conn.prepare("SELECT COUNT(*) FROM counters;")?;
         if let Some(row) = stmt.iter().next() {
@@ -73,12 +217,84 @@ impl Connection {
 
         Ok(())
     }
+
+    fn migrate(&mut self) -> Result<()> {
+        let mut stmt = self.conn.prepare("PRAGMA user_version;")?;
+        stmt.next()?;
+        let version = stmt.read::<i64, _>(0)? as usize;
+        drop(stmt);
+
+        for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            self.conn.execute("BEGIN;")?;
+            let applied = self.conn.execute(migration).and_then(|_| {
+                self.conn
+                    .execute(format!("PRAGMA user_version = {};", i + 1))
+            });
+            match applied {
+                Ok(()) => self.conn.execute("COMMIT;")?,
+                Err(e) => {
+                    self.conn.execute("ROLLBACK;")?;
+                    bail!("failed to upgrade the database to version {}: {e}", i + 1);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Open the database for reading without taking the file lock or creating
+/// anything, for callers that must not block or mutate.
+pub fn open_read_only(name: &str) -> Result<ConnectionThreadSafe> {
+    let flags = sqlite::OpenFlags::new().with_read_only();
+    Ok(sqlite::Connection::open_thread_safe_with_flags(
+        name, flags,
+    )?)
 }
 
 impl Drop for Connection {
     fn drop(&mut self) {
-        if let Err(e) = fs2::FileExt::unlock(&self.lock_file) {
-            eprintln!("Warning: Failed to unlock file: {}", e);
+        if let Some(lock) = &self.lock {
+            if let Err(e) = FileExt::unlock(&lock.file) {
+                eprintln!("Warning: Failed to unlock file: {}", e);
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn upgrades_databases_from_before_migrations() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("old.db").to_string_lossy().into_owned();
+        let old = sqlite::open(&path).unwrap();
+        old.execute(
+            "
+            CREATE TABLE counters (
+                name TEXT PRIMARY KEY,
+                count INTEGER NOT NULL,
+                step INTEGER NOT NULL,
+                template TEXT NOT NULL
+            );
+            INSERT INTO counters VALUES ('old', 7, 2, 'v{}');
+            ",
+        )
+        .unwrap();
+        drop(old);
+
+        let conn = Connection::new(&path).unwrap();
+        let counter = Counter::get(conn.get(), "old").unwrap().unwrap();
+        assert_eq!((counter.count, counter.step), (7, 2));
+        assert_eq!(counter.kind, crate::models::Kind::Counter);
+        drop(conn);
+
+        // Opening again finds nothing left to do
+        let conn = Connection::new(&path).unwrap();
+        let mut stmt = conn.get().prepare("PRAGMA user_version;").unwrap();
+        stmt.next().unwrap();
+        assert_eq!(stmt.read::<i64, _>(0).unwrap(), MIGRATIONS.len() as i64);
+    }
+}
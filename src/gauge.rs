@@ -0,0 +1,97 @@
+use crate::template::Value;
+use anyhow::{anyhow, Result};
+use std::fmt;
+use std::str::FromStr;
+
+/// Running statistics of a counter that's set rather than added to, such
+/// as a queue depth. The last value is the counter's count.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Gauge {
+    pub min: i64,
+    pub max: i64,
+    pub sum: i128,
+    pub samples: u64,
+}
+
+impl Gauge {
+    /// Statistics of a single value.
+    pub fn new(value: i64) -> Gauge {
+        Gauge {
+            min: value,
+            max: value,
+            sum: value.into(),
+            samples: 1,
+        }
+    }
+
+    pub fn record(&mut self, value: i64) {
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.sum += i128::from(value);
+        self.samples += 1;
+    }
+
+    pub fn avg(&self) -> f64 {
+        self.sum as f64 / self.samples as f64
+    }
+
+    /// Statistic by name, for `{q.max}` in templates.
+    pub fn field(&self, name: &str) -> Option<Value> {
+        Some(match name {
+            "min" => Value::Int(self.min),
+            "max" => Value::Int(self.max),
+            "avg" => Value::Float(self.avg()),
+            "samples" => Value::Int(i64::try_from(self.samples).ok()?),
+            _ => return None,
+        })
+    }
+}
+
+impl FromStr for Gauge {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Gauge> {
+        let invalid = || anyhow!("invalid gauge statistics '{s}'");
+        let parts: Vec<_> = s.split(' ').collect();
+        let [min, max, sum, samples] = parts[..] else {
+            return Err(invalid());
+        };
+        Ok(Gauge {
+            min: min.parse().map_err(|_| invalid())?,
+            max: max.parse().map_err(|_| invalid())?,
+            sum: sum.parse().map_err(|_| invalid())?,
+            samples: samples.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+impl fmt::Display for Gauge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} {} {}", self.min, self.max, self.sum, self.samples)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_statistics() {
+        let mut g = Gauge::new(4);
+        g.record(10);
+        g.record(-2);
+        assert_eq!((g.min, g.max, g.samples), (-2, 10, 3));
+        assert_eq!(g.field("avg"), Some(Value::Float(4.0)));
+        assert_eq!(g.field("max"), Some(Value::Int(10)));
+        assert_eq!(g.field("last"), None);
+    }
+
+    #[test]
+    fn state_round_trips() {
+        let mut g = Gauge::new(i64::MAX);
+        g.record(i64::MAX);
+        assert_eq!(g.to_string().parse::<Gauge>().unwrap(), g);
+        assert!("1 2 3".parse::<Gauge>().is_err());
+        assert!("a b c d".parse::<Gauge>().is_err());
+    }
+}
@@ -0,0 +1,141 @@
+use anyhow::{anyhow, bail, Context, Result};
+use clap::ValueEnum;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// What a scoped counter keeps a separate value for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Scope {
+    /// The repository and the branch checked out in it
+    GitBranch,
+    /// The repository, whatever is checked out
+    Repo,
+}
+
+/// A git checkout found by walking up from a directory.
+#[derive(Debug, PartialEq)]
+pub struct Repo {
+    /// Top level of the working tree
+    pub root: PathBuf,
+    /// Branch checked out, `None` when the HEAD is detached
+    pub head: Option<String>,
+}
+
+impl Repo {
+    /// Find the checkout containing `dir` by reading `.git` directly, so
+    /// neither git nor a network is needed.
+    pub fn discover(dir: &Path) -> Result<Repo> {
+        let dir = dir
+            .canonicalize()
+            .with_context(|| format!("couldn't resolve {}", dir.display()))?;
+        for root in dir.ancestors() {
+            let dot_git = root.join(".git");
+            let git_dir = if dot_git.is_dir() {
+                dot_git
+            } else if dot_git.is_file() {
+                // Worktrees and submodules point at their git directory
+                let contents = fs::read_to_string(&dot_git)?;
+                let target = contents
+                    .trim()
+                    .strip_prefix("gitdir:")
+                    .ok_or_else(|| anyhow!("{} isn't a gitdir link", dot_git.display()))?;
+                root.join(target.trim())
+            } else {
+                continue;
+            };
+            let head_path = git_dir.join("HEAD");
+            let head = fs::read_to_string(&head_path)
+                .with_context(|| format!("couldn't read {}", head_path.display()))?;
+            let head = head.trim();
+            if head.is_empty() {
+                bail!("{} is empty", head_path.display());
+            }
+            // Anything but a ref is a commit checked out directly
+            let head = head.strip_prefix("ref:").map(|reference| {
+                let reference = reference.trim();
+                reference
+                    .strip_prefix("refs/heads/")
+                    .unwrap_or(reference)
+                    .to_string()
+            });
+            return Ok(Repo {
+                root: root.to_path_buf(),
+                head,
+            });
+        }
+        bail!("{} isn't inside a git repository", dir.display())
+    }
+
+    /// Key naming this checkout's value of a counter scoped by `scope`. A
+    /// detached HEAD has no branch, and keying by commit would start every
+    /// commit from 0.
+    pub fn key(&self, scope: Scope) -> Result<String> {
+        let root = self.root.to_string_lossy();
+        match (scope, &self.head) {
+            (Scope::Repo, _) => Ok(root.into_owned()),
+            // Ref names can't contain ':', so this can't be ambiguous
+            (Scope::GitBranch, Some(branch)) => Ok(format!("{root}:{branch}")),
+            (Scope::GitBranch, None) => bail!(
+                "the HEAD of {root} is detached, so there's no branch to scope by; check out a branch or use --scope repo"
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn repo_with_head(head: &str) -> TempDir {
+        let dir = TempDir::new().unwrap();
+        fs::create_dir_all(dir.path().join(".git")).unwrap();
+        fs::write(dir.path().join(".git/HEAD"), head).unwrap();
+        dir
+    }
+
+    #[test]
+    fn finds_branch_from_subdirectory() {
+        let dir = repo_with_head("ref: refs/heads/feature/login\n");
+        let nested = dir.path().join("src/deep");
+        fs::create_dir_all(&nested).unwrap();
+
+        let repo = Repo::discover(&nested).unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        assert_eq!(repo.root, root);
+        assert_eq!(repo.head.as_deref(), Some("feature/login"));
+        assert_eq!(repo.key(Scope::Repo).unwrap(), root.to_string_lossy());
+        assert_eq!(
+            repo.key(Scope::GitBranch).unwrap(),
+            format!("{}:feature/login", root.to_string_lossy())
+        );
+    }
+
+    #[test]
+    fn detached_head_has_no_branch() {
+        let dir = repo_with_head("0123456789abcdef0123456789abcdef01234567\n");
+        let repo = Repo::discover(dir.path()).unwrap();
+        assert_eq!(repo.head, None);
+        let e = repo.key(Scope::GitBranch).unwrap_err();
+        assert!(e.to_string().contains("is detached"), "{e}");
+        assert!(repo.key(Scope::Repo).is_ok());
+    }
+
+    #[test]
+    fn follows_gitdir_links() {
+        let main = repo_with_head("ref: refs/heads/main\n");
+        let git_dir = main.path().join(".git/worktrees/wt");
+        fs::create_dir_all(&git_dir).unwrap();
+        fs::write(git_dir.join("HEAD"), "ref: refs/heads/topic\n").unwrap();
+        let worktree = TempDir::new().unwrap();
+        fs::write(
+            worktree.path().join(".git"),
+            format!("gitdir: {}\n", git_dir.display()),
+        )
+        .unwrap();
+
+        let repo = Repo::discover(worktree.path()).unwrap();
+        assert_eq!(repo.head.as_deref(), Some("topic"));
+        assert_eq!(repo.root, worktree.path().canonicalize().unwrap());
+    }
+}
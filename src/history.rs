@@ -0,0 +1,387 @@
+use anyhow::{anyhow, bail, Result};
+use chrono::DateTime;
+use sqlite::{ConnectionThreadSafe, State};
+use std::str::FromStr;
+
+/// Most buckets a summary will split its window into.
+const MAX_BUCKETS: i64 = 10_000;
+
+/// Sparkline levels, lowest first.
+const SPARKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// Bar endings for eighths of a cell, an empty one first.
+const EIGHTHS: [&str; 8] = ["", "▏", "▎", "▍", "▌", "▋", "▊", "▉"];
+
+/// A length of time such as `90s`, `15m`, `12h`, `7d` or `2w`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub seconds: i64,
+}
+
+impl FromStr for Span {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Span> {
+        let invalid =
+            || anyhow!("invalid duration '{s}', expected a number and one of s, m, h, d or w");
+        let unit = s.chars().last().ok_or_else(invalid)?;
+        let scale = match unit {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            'w' => 7 * 24 * 60 * 60,
+            _ => return Err(invalid()),
+        };
+        let n: i64 = s[..s.len() - 1].parse().map_err(|_| invalid())?;
+        match n.checked_mul(scale) {
+            Some(seconds) if seconds > 0 => Ok(Span { seconds }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// One change of a counter's count.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Entry {
+    /// Unix time of the change
+    pub timestamp: i64,
+    /// Count after the change
+    pub count: i64,
+    /// How much the change moved the count
+    pub delta: i64,
+}
+
+pub fn record(
+    conn: &ConnectionThreadSafe,
+    name: &str,
+    scope: Option<&str>,
+    entry: Entry,
+) -> sqlite::Result<()> {
+    let mut stmt = conn.prepare(
+        "INSERT INTO history (name, scope, timestamp, count, delta) VALUES (?, ?, ?, ?, ?)",
+    )?;
+    stmt.bind((1, name))?;
+    stmt.bind((2, scope.unwrap_or("")))?;
+    stmt.bind((3, entry.timestamp))?;
+    stmt.bind((4, entry.count))?;
+    stmt.bind((5, entry.delta))?;
+    stmt.next()?;
+    Ok(())
+}
+
+/// Changes of a counter at or after `since`, oldest first.
+pub fn load(
+    conn: &ConnectionThreadSafe,
+    name: &str,
+    scope: Option<&str>,
+    since: i64,
+) -> sqlite::Result<Vec<Entry>> {
+    let mut stmt = conn.prepare(
+        "SELECT timestamp, count, delta FROM history
+         WHERE name = ? AND scope = ? AND timestamp >= ?
+         ORDER BY timestamp, rowid",
+    )?;
+    stmt.bind((1, name))?;
+    stmt.bind((2, scope.unwrap_or("")))?;
+    stmt.bind((3, since))?;
+    let mut entries = Vec::new();
+    while let State::Row = stmt.next()? {
+        entries.push(Entry {
+            timestamp: stmt.read::<i64, usize>(0)?,
+            count: stmt.read::<i64, usize>(1)?,
+            delta: stmt.read::<i64, usize>(2)?,
+        });
+    }
+    Ok(entries)
+}
+
+/// The latest `limit` changes of a counter, oldest first.
+pub fn recent(
+    conn: &ConnectionThreadSafe,
+    name: &str,
+    scope: Option<&str>,
+    limit: usize,
+) -> sqlite::Result<Vec<Entry>> {
+    let mut stmt = conn.prepare(
+        "SELECT timestamp, count, delta FROM history
+         WHERE name = ? AND scope = ?
+         ORDER BY timestamp DESC, rowid DESC LIMIT ?",
+    )?;
+    stmt.bind((1, name))?;
+    stmt.bind((2, scope.unwrap_or("")))?;
+    stmt.bind((3, i64::try_from(limit).unwrap_or(i64::MAX)))?;
+    let mut entries = Vec::new();
+    while let State::Row = stmt.next()? {
+        entries.push(Entry {
+            timestamp: stmt.read::<i64, usize>(0)?,
+            count: stmt.read::<i64, usize>(1)?,
+            delta: stmt.read::<i64, usize>(2)?,
+        });
+    }
+    entries.reverse();
+    Ok(entries)
+}
+
+/// Changes falling in one bucket of time.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bucket {
+    /// Unix time the bucket starts at
+    pub start: i64,
+    /// Widened so that changes near the limits of a count add up
+    pub sum: i128,
+    pub changes: usize,
+}
+
+impl Bucket {
+    /// The start as UTC, `2026-10-19 00:00`.
+    pub fn label(&self) -> String {
+        DateTime::from_timestamp(self.start, 0)
+            .map(|t| t.format("%Y-%m-%d %H:%M").to_string())
+            .unwrap_or_else(|| self.start.to_string())
+    }
+}
+
+/// What the changes in a window of time add up to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Summary {
+    pub changes: usize,
+    pub total: i128,
+    /// Mean size of a change
+    pub mean: Option<f64>,
+    /// Median, 90th and 99th percentile size of a change
+    pub percentiles: Option<[i64; 3]>,
+    /// Total change per day over the window
+    pub rate_per_day: f64,
+    pub buckets: Vec<Bucket>,
+}
+
+/// Summarize `entries` over the window from `from` to `to`, split into
+/// buckets of `bucket` aligned to the unix epoch if given.
+pub fn summarize(entries: &[Entry], from: i64, to: i64, bucket: Option<Span>) -> Result<Summary> {
+    let deltas: Vec<i64> = entries.iter().map(|e| e.delta).collect();
+    let total: i128 = deltas.iter().map(|&d| i128::from(d)).sum();
+    let mean = (!deltas.is_empty()).then(|| total as f64 / deltas.len() as f64);
+    let percentiles = (!deltas.is_empty()).then(|| {
+        let mut sorted = deltas.clone();
+        sorted.sort_unstable();
+        [50, 90, 99].map(|p| nearest_rank(&sorted, p))
+    });
+    let days = (to - from).max(1) as f64 / (24 * 60 * 60) as f64;
+
+    let mut buckets = Vec::new();
+    if let Some(size) = bucket {
+        buckets = bucket_starts(from, to, size)?
+            .map(|start| Bucket {
+                start,
+                sum: 0,
+                changes: 0,
+            })
+            .collect();
+        for entry in entries {
+            if let Some(bucket) =
+                bucket_index(&buckets, entry.timestamp, size).and_then(|i| buckets.get_mut(i))
+            {
+                bucket.sum += i128::from(entry.delta);
+                bucket.changes += 1;
+            }
+        }
+    }
+
+    Ok(Summary {
+        changes: deltas.len(),
+        total,
+        mean,
+        percentiles,
+        rate_per_day: total as f64 / days,
+        buckets,
+    })
+}
+
+/// The count at the end of each bucket from `from` to `to`, carrying the
+/// last count through buckets without changes. `current` is the count when
+/// there are no changes to go by.
+pub fn series(
+    entries: &[Entry],
+    current: i64,
+    from: i64,
+    to: i64,
+    size: Span,
+) -> Result<Vec<(Bucket, i64)>> {
+    // The count before the first change, or just the count if that can't
+    // have been an i64
+    let mut value = entries
+        .first()
+        .map_or(current, |e| e.count.checked_sub(e.delta).unwrap_or(e.count));
+    let mut entries = entries.iter().peekable();
+    let mut points = Vec::new();
+    for start in bucket_starts(from, to, size)? {
+        let mut bucket = Bucket {
+            start,
+            sum: 0,
+            changes: 0,
+        };
+        while let Some(entry) = entries.next_if(|e| e.timestamp < start + size.seconds) {
+            value = entry.count;
+            bucket.sum += i128::from(entry.delta);
+            bucket.changes += 1;
+        }
+        points.push((bucket, value));
+    }
+    Ok(points)
+}
+
+/// Starts of the buckets of `size` covering `from` to `to`, aligned to
+/// the unix epoch.
+fn bucket_starts(from: i64, to: i64, size: Span) -> Result<impl Iterator<Item = i64>> {
+    let size = size.seconds;
+    let first = from.div_euclid(size) * size;
+    let count = (to - first).div_euclid(size) + 1;
+    if count > MAX_BUCKETS {
+        bail!("that's {count} buckets, use a bigger bucket or a shorter --since");
+    }
+    Ok((0..count.max(0)).map(move |i| first + i * size))
+}
+
+fn bucket_index(buckets: &[Bucket], timestamp: i64, size: Span) -> Option<usize> {
+    let first = buckets.first()?.start;
+    usize::try_from((timestamp - first).div_euclid(size.seconds)).ok()
+}
+
+/// One character per value, taller for bigger ones.
+pub fn sparkline(values: &[i64]) -> String {
+    let (Some(&min), Some(&max)) = (values.iter().min(), values.iter().max()) else {
+        return String::new();
+    };
+    let range = i128::from(max) - i128::from(min);
+    values
+        .iter()
+        .map(|&v| {
+            let level = match range {
+                0 => 0,
+                _ => (i128::from(v) - i128::from(min)) * 7 / range,
+            };
+            SPARKS[level as usize]
+        })
+        .collect()
+}
+
+/// A horizontal bar `width` cells long at `max`, in eighths of a cell.
+pub fn bar(value: i64, min: i64, max: i64, width: usize) -> String {
+    let range = (i128::from(max) - i128::from(min)).max(1);
+    let eighths = (i128::from(value) - i128::from(min)) * (width as i128 * 8) / range;
+    let eighths = eighths.clamp(0, width as i128 * 8) as usize;
+    "█".repeat(eighths / 8) + EIGHTHS[eighths % 8]
+}
+
+/// The `p`th percentile of sorted, non-empty `values`.
+fn nearest_rank(values: &[i64], p: usize) -> i64 {
+    let rank = (p * values.len()).div_ceil(100).max(1);
+    values[rank - 1]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: i64 = 24 * 60 * 60;
+
+    fn entry(timestamp: i64, delta: i64) -> Entry {
+        Entry {
+            timestamp,
+            count: 0,
+            delta,
+        }
+    }
+
+    #[test]
+    fn parses_spans() {
+        assert_eq!("90s".parse::<Span>().unwrap().seconds, 90);
+        assert_eq!("7d".parse::<Span>().unwrap().seconds, 7 * DAY);
+        assert_eq!("2w".parse::<Span>().unwrap().seconds, 14 * DAY);
+        for s in ["", "d", "7", "0d", "-1d", "1.5h", "7y"] {
+            assert!(s.parse::<Span>().is_err(), "{s}");
+        }
+    }
+
+    #[test]
+    fn summarizes_changes() {
+        let entries = [
+            entry(DAY + 10, 1),
+            entry(DAY + 20, 1),
+            entry(2 * DAY + 5, 4),
+            entry(3 * DAY + 1, 10),
+        ];
+        let day = "1d".parse().unwrap();
+        let summary = summarize(&entries, DAY, 4 * DAY - 1, Some(day)).unwrap();
+        assert_eq!(summary.changes, 4);
+        assert_eq!(summary.total, 16);
+        assert_eq!(summary.mean, Some(4.0));
+        assert_eq!(summary.percentiles, Some([1, 10, 10]));
+        let sums: Vec<_> = summary.buckets.iter().map(|b| b.sum).collect();
+        assert_eq!(sums, [2, 4, 10]);
+        assert_eq!(summary.buckets[0].label(), "1970-01-02 00:00");
+    }
+
+    #[test]
+    fn extreme_changes_add_up() {
+        let entries = [entry(DAY, i64::MAX), entry(DAY + 1, i64::MAX)];
+        let day = "1d".parse().unwrap();
+        let summary = summarize(&entries, DAY, 2 * DAY - 1, Some(day)).unwrap();
+        assert_eq!(summary.total, 2 * i128::from(i64::MAX));
+        assert_eq!(summary.buckets[0].sum, summary.total);
+
+        let entries = [Entry {
+            timestamp: DAY,
+            count: i64::MIN,
+            delta: 1,
+        }];
+        let points = series(&entries, 0, DAY, DAY, day).unwrap();
+        assert_eq!(points[0].1, i64::MIN);
+    }
+
+    #[test]
+    fn series_carries_counts_forward() {
+        let entries = [
+            Entry {
+                timestamp: DAY + 10,
+                count: 5,
+                delta: 2,
+            },
+            Entry {
+                timestamp: 3 * DAY,
+                count: 9,
+                delta: 4,
+            },
+        ];
+        let day = "1d".parse().unwrap();
+        let values: Vec<_> = series(&entries, 9, 0, 3 * DAY, day)
+            .unwrap()
+            .into_iter()
+            .map(|(_, v)| v)
+            .collect();
+        assert_eq!(values, [3, 5, 5, 9]);
+        let flat = series(&[], 7, 0, DAY, day).unwrap();
+        assert_eq!(flat.iter().map(|(_, v)| *v).collect::<Vec<_>>(), [7, 7]);
+    }
+
+    #[test]
+    fn draws_sparklines_and_bars() {
+        assert_eq!(sparkline(&[0, 7, 14, 7]), "▁▄█▄");
+        assert_eq!(sparkline(&[3, 3]), "▁▁");
+        assert_eq!(sparkline(&[]), "");
+        assert_eq!(bar(10, 0, 10, 4), "████");
+        assert_eq!(bar(5, 0, 10, 3), "█▌");
+        assert_eq!(bar(0, 0, 10, 4), "");
+    }
+
+    #[test]
+    fn empty_windows_have_no_mean() {
+        let day = "1d".parse().unwrap();
+        let summary = summarize(&[], 0, 2 * DAY, Some(day)).unwrap();
+        assert_eq!(summary.mean, None);
+        assert_eq!(summary.percentiles, None);
+        assert_eq!(summary.buckets.len(), 3);
+        assert!(summarize(&[], 0, 365 * DAY, Some("1s".parse().unwrap())).is_err());
+    }
+}
@@ -0,0 +1,217 @@
+use anyhow::{bail, Context, Result};
+use sqlite::{ConnectionThreadSafe, State};
+use std::fmt;
+use std::io;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::thread::{self, JoinHandle};
+
+/// When a hook runs, judged by the count before and after a change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trigger {
+    /// The count goes from at most the value to over it
+    Above(i64),
+    /// The count goes from at least the value to under it
+    Below(i64),
+    /// The count lands on the value
+    Equals(i64),
+    /// The count passes a multiple of the value
+    Every(i64),
+}
+
+impl Trigger {
+    pub fn fires(&self, before: i64, after: i64) -> bool {
+        match *self {
+            Trigger::Above(n) => before <= n && after > n,
+            Trigger::Below(n) => before >= n && after < n,
+            Trigger::Equals(n) => before != n && after == n,
+            Trigger::Every(n) => before.div_euclid(n) != after.div_euclid(n),
+        }
+    }
+
+    /// Name and value stored in the `hooks` table.
+    fn parts(&self) -> (&'static str, i64) {
+        match *self {
+            Trigger::Above(n) => ("above", n),
+            Trigger::Below(n) => ("below", n),
+            Trigger::Equals(n) => ("equals", n),
+            Trigger::Every(n) => ("every", n),
+        }
+    }
+
+    fn load(kind: &str, value: i64) -> sqlite::Result<Trigger> {
+        Ok(match kind {
+            "above" => Trigger::Above(value),
+            "below" => Trigger::Below(value),
+            "equals" => Trigger::Equals(value),
+            "every" if value > 0 => Trigger::Every(value),
+            _ => {
+                return Err(sqlite::Error {
+                    code: None,
+                    message: Some(format!("invalid hook trigger '{kind} {value}'")),
+                })
+            }
+        })
+    }
+}
+
+impl fmt::Display for Trigger {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (kind, value) = self.parts();
+        write!(f, "{kind} {value}")
+    }
+}
+
+/// A command to run when a counter's count crosses a threshold.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hook {
+    pub id: i64,
+    pub name: String,
+    pub trigger: Trigger,
+    pub command: String,
+}
+
+impl Hook {
+    pub fn add(
+        conn: &ConnectionThreadSafe,
+        name: &str,
+        trigger: Trigger,
+        command: &str,
+    ) -> Result<i64> {
+        if let Trigger::Every(n) = trigger {
+            if n <= 0 {
+                bail!("--every needs a positive number, got {n}");
+            }
+        }
+        let (kind, value) = trigger.parts();
+        let mut stmt = conn.prepare(
+            "INSERT INTO hooks (name, trigger, value, command) VALUES (?, ?, ?, ?) RETURNING id",
+        )?;
+        stmt.bind((1, name))?;
+        stmt.bind((2, kind))?;
+        stmt.bind((3, value))?;
+        stmt.bind((4, command))?;
+        stmt.next()?;
+        Ok(stmt.read::<i64, usize>(0)?)
+    }
+
+    /// Hooks of a counter in the order they were added.
+    pub fn for_counter(conn: &ConnectionThreadSafe, name: &str) -> sqlite::Result<Vec<Hook>> {
+        let mut stmt = conn.prepare(
+            "SELECT id, name, trigger, value, command FROM hooks WHERE name = ? ORDER BY id",
+        )?;
+        stmt.bind((1, name))?;
+        let mut hooks = Vec::new();
+        while let State::Row = stmt.next()? {
+            hooks.push(Hook {
+                id: stmt.read::<i64, usize>(0)?,
+                name: stmt.read::<String, usize>(1)?,
+                trigger: Trigger::load(
+                    &stmt.read::<String, usize>(2)?,
+                    stmt.read::<i64, usize>(3)?,
+                )?,
+                command: stmt.read::<String, usize>(4)?,
+            });
+        }
+        Ok(hooks)
+    }
+
+    /// Remove a hook of the counter, returning whether there was one.
+    pub fn remove(conn: &ConnectionThreadSafe, name: &str, id: i64) -> sqlite::Result<bool> {
+        let mut stmt = conn.prepare("DELETE FROM hooks WHERE name = ? AND id = ? RETURNING id")?;
+        stmt.bind((1, name))?;
+        stmt.bind((2, id))?;
+        Ok(matches!(stmt.next()?, State::Row))
+    }
+
+    /// Start the command in the background with the change in its
+    /// environment. Only a thread waits for it, so it may run tally itself
+    /// once this process lets go of the database.
+    pub fn spawn(&self, scope: Option<&str>, before: i64, after: i64) -> Result<()> {
+        let mut command = shell(&self.command);
+        command
+            .env("TALLY_COUNTER", &self.name)
+            .env("TALLY_VALUE", after.to_string())
+            .env("TALLY_PREVIOUS", before.to_string())
+            .env("TALLY_TRIGGER", self.trigger.to_string())
+            .stdin(Stdio::null())
+            .stdout(Stdio::null());
+        if let Some(scope) = scope {
+            command.env("TALLY_SCOPE", scope);
+        }
+        let child = command.spawn().with_context(|| {
+            format!(
+                "failed to run hook {} of counter '{}': {}",
+                self.id, self.name, self.command
+            )
+        })?;
+        reap(child);
+        Ok(())
+    }
+}
+
+/// Wait for `child` on a thread of its own, so a long-running `serve`
+/// doesn't collect zombies. A short-lived process simply leaves it behind.
+fn reap(mut child: Child) -> JoinHandle<io::Result<ExitStatus>> {
+    thread::spawn(move || child.wait())
+}
+
+#[cfg(unix)]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command);
+    shell
+}
+
+#[cfg(windows)]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.arg("/C").arg(command);
+    shell
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::Connection;
+    use tempfile::TempDir;
+
+    #[test]
+    fn triggers_fire_on_crossing() {
+        assert!(Trigger::Above(100).fires(100, 101));
+        assert!(!Trigger::Above(100).fires(101, 102));
+        assert!(!Trigger::Above(100).fires(90, 100));
+        assert!(Trigger::Below(0).fires(1, -1));
+        assert!(!Trigger::Below(0).fires(-1, -2));
+        assert!(Trigger::Equals(5).fires(4, 5));
+        assert!(!Trigger::Equals(5).fires(5, 5));
+        assert!(Trigger::Every(10).fires(9, 10));
+        assert!(Trigger::Every(10).fires(10, 9));
+        assert!(Trigger::Every(10).fires(-1, 0));
+        assert!(!Trigger::Every(10).fires(11, 19));
+    }
+
+    #[test]
+    fn finished_hooks_are_waited_for() {
+        let child = shell("exit 3").spawn().unwrap();
+        let status = reap(child).join().unwrap().unwrap();
+        assert_eq!(status.code(), Some(3));
+    }
+
+    #[test]
+    fn hooks_are_stored_per_counter() {
+        let dir = TempDir::new().unwrap();
+        let conn = Connection::new(&dir.path().join("t.db").to_string_lossy()).unwrap();
+        let id = Hook::add(conn.get(), "retries", Trigger::Above(3), "echo hi").unwrap();
+        Hook::add(conn.get(), "other", Trigger::Every(2), "true").unwrap();
+        assert!(Hook::add(conn.get(), "other", Trigger::Every(0), "true").is_err());
+
+        let hooks = Hook::for_counter(conn.get(), "retries").unwrap();
+        assert_eq!(hooks.len(), 1);
+        assert_eq!(hooks[0].trigger.to_string(), "above 3");
+        assert_eq!(hooks[0].command, "echo hi");
+
+        assert!(!Hook::remove(conn.get(), "other", id).unwrap());
+        assert!(Hook::remove(conn.get(), "retries", id).unwrap());
+        assert!(Hook::for_counter(conn.get(), "retries").unwrap().is_empty());
+    }
+}
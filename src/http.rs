@@ -0,0 +1,430 @@
+use crate::app;
+use crate::cli::{AmountArgs, Cli, Commands, SetArgs};
+use crate::config::Config;
+use crate::daemon;
+use crate::database::{Connection, LockTimeout};
+use crate::models::Counter;
+use crate::template;
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::io::Read;
+use std::path::Path;
+use std::time::Duration;
+use tiny_http::{Header, Method, Response, Server};
+
+/// Largest request body read, counters never need more.
+const MAX_BODY: u64 = 64 * 1024;
+
+/// Body of `POST /counters/{name}`, the same settings as `tally set`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SetBody {
+    count: Option<i64>,
+    step: Option<i64>,
+    template: Option<String>,
+    #[serde(default)]
+    default: bool,
+}
+
+/// Body of `POST /counters/{name}/add`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AddBody {
+    amount: Option<i64>,
+}
+
+/// An answer other than success, sent as `{"error": message}`.
+#[derive(Debug, PartialEq)]
+struct Failure {
+    status: u16,
+    message: String,
+}
+
+impl Failure {
+    fn new(status: u16, message: impl Into<String>) -> Failure {
+        Failure {
+            status,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<anyhow::Error> for Failure {
+    /// Blame the client unless the database or the lock let us down.
+    fn from(e: anyhow::Error) -> Failure {
+        let status = if e.chain().any(|cause| cause.is::<LockTimeout>()) {
+            503
+        } else if e
+            .chain()
+            .any(|cause| cause.is::<sqlite::Error>() || cause.is::<std::io::Error>())
+        {
+            500
+        } else {
+            400
+        };
+        Failure::new(status, format!("{e:#}"))
+    }
+}
+
+/// The counters as a JSON API, changed the same way as by the commands.
+struct Api<'a> {
+    config: &'a Config,
+    conn: Connection,
+    database_path: &'a Path,
+    lock_timeout: Option<Duration>,
+    token: Option<&'a str>,
+}
+
+/// Answer HTTP requests on `address` until killed, one at a time and
+/// holding the lock only while answering.
+pub fn serve(
+    config: &Config,
+    conn: Connection,
+    database_path: &Path,
+    lock_timeout: Option<Duration>,
+    address: &str,
+    token: Option<&str>,
+) -> Result<()> {
+    let server =
+        Server::http(address).map_err(|e| anyhow!("failed to listen on {address}: {e}"))?;
+    if let Some(address) = server.server_addr().to_ip() {
+        eprintln!("tally: serving on http://{address}");
+    }
+    conn.unlock()?;
+
+    let api = Api {
+        config,
+        conn,
+        database_path,
+        lock_timeout,
+        token,
+    };
+    for mut request in server.incoming_requests() {
+        let authorization = request
+            .headers()
+            .iter()
+            .find(|header| header.field.equiv("Authorization"))
+            .map(|header| header.value.to_string());
+        let mut body = String::new();
+        let result = match request.as_reader().take(MAX_BODY).read_to_string(&mut body) {
+            Ok(_) => api.handle(
+                request.method(),
+                request.url(),
+                authorization.as_deref(),
+                &body,
+            ),
+            Err(e) => Err(Failure::new(400, format!("unreadable body: {e}"))),
+        };
+
+        let (status, body) = match result {
+            Ok(value) => (200, value),
+            Err(failure) => (failure.status, json!({ "error": failure.message })),
+        };
+        let mut response = Response::from_string(body.to_string())
+            .with_status_code(status)
+            .with_header(header("Content-Type", "application/json"));
+        if status == 401 {
+            response.add_header(header("WWW-Authenticate", "Bearer"));
+        }
+        if let Err(e) = request.respond(response) {
+            eprintln!("tally: {e}");
+        }
+    }
+    Ok(())
+}
+
+fn header(field: &str, value: &str) -> Header {
+    Header::from_bytes(field.as_bytes(), value.as_bytes()).expect("valid header")
+}
+
+impl Api<'_> {
+    fn handle(
+        &self,
+        method: &Method,
+        url: &str,
+        authorization: Option<&str>,
+        body: &str,
+    ) -> Result<Value, Failure> {
+        if let Some(token) = self.token {
+            let sent = authorization.and_then(|value| value.strip_prefix("Bearer "));
+            if !sent.is_some_and(|sent| same(sent, token)) {
+                return Err(Failure::new(401, "missing or wrong bearer token"));
+            }
+        }
+
+        let path = url.split('?').next().unwrap_or_default();
+        let segments = path
+            .trim_matches('/')
+            .split('/')
+            .map(decode)
+            .collect::<Result<Vec<_>, _>>()?;
+        let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+        let allowed = match segments.as_slice() {
+            ["counters"] => "GET",
+            ["counters", _] => "GET, POST",
+            ["counters", _, "add"] => "POST",
+            _ => return Err(Failure::new(404, format!("no such endpoint '{path}'"))),
+        };
+        daemon::locked(&self.conn, self.lock_timeout, || {
+            Ok(match (method, segments.as_slice()) {
+                (Method::Get, ["counters"]) => Ok(self.list()?),
+                (Method::Get, [_, name]) => self.get(name),
+                (Method::Post, [_, name]) => {
+                    let body: SetBody = parse(body)?;
+                    self.set(name, body)
+                }
+                (Method::Post, [_, name, _]) => {
+                    let body: AddBody = parse(body)?;
+                    self.run(
+                        name,
+                        Commands::Add(AmountArgs {
+                            amount: body.amount,
+                        }),
+                    )
+                }
+                _ => Err(Failure::new(405, format!("{path} only allows {allowed}"))),
+            })
+        })?
+    }
+
+    fn list(&self) -> Result<Value> {
+        let default = Counter::get_default(self.conn.get())?.unwrap_or_default();
+        let counters: Vec<_> = Counter::get_all(self.conn.get())?
+            .iter()
+            .map(|counter| app::counter_json(&self.conn, counter, &default))
+            .collect();
+        Ok(Value::Array(counters))
+    }
+
+    fn get(&self, name: &str) -> Result<Value, Failure> {
+        let counter = Counter::get(self.conn.get(), name)
+            .map_err(anyhow::Error::from)?
+            .ok_or_else(|| Failure::new(404, format!("counter '{name}' does not exist")))?;
+        let default = Counter::get_default(self.conn.get())
+            .map_err(anyhow::Error::from)?
+            .unwrap_or_default();
+        Ok(app::counter_json(&self.conn, &counter, &default))
+    }
+
+    fn set(&self, name: &str, body: SetBody) -> Result<Value, Failure> {
+        if let Some(template) = &body.template {
+            template::validate(template)
+                .with_context(|| format!("invalid template '{template}'"))?;
+        }
+        self.run(
+            name,
+            Commands::Set(SetArgs {
+                count: body.count,
+                step: body.step,
+                template: body.template,
+                default: body.default,
+                ..SetArgs::default()
+            }),
+        )
+    }
+
+    /// Run a command on the counter, answering with the counter after it.
+    fn run(&self, name: &str, command: Commands) -> Result<Value, Failure> {
+        let cli = Cli {
+            name: Some(name.to_string()),
+            raw: false,
+            quiet: true,
+            database: None,
+            strict: false,
+            scope: None,
+            command: Some(command),
+        };
+        app::counter_command(
+            self.config,
+            &self.conn,
+            self.database_path,
+            cli,
+            None,
+            &mut std::io::sink(),
+        )?;
+        self.get(name)
+    }
+}
+
+/// Read a JSON body, an empty one standing for `{}`.
+fn parse<T: Default + for<'de> Deserialize<'de>>(body: &str) -> Result<T> {
+    if body.trim().is_empty() {
+        return Ok(T::default());
+    }
+    serde_json::from_str(body).context("invalid request body")
+}
+
+/// Undo the percent-encoding of a path segment, `my%20build`.
+fn decode(segment: &str) -> Result<String, Failure> {
+    let invalid = || Failure::new(400, format!("invalid percent-encoding in '{segment}'"));
+    let mut bytes = Vec::with_capacity(segment.len());
+    let mut rest = segment.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = tail
+                .get(..2)
+                .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+                .ok_or_else(invalid)?;
+            let hex = std::str::from_utf8(hex).map_err(|_| invalid())?;
+            bytes.push(u8::from_str_radix(hex, 16).map_err(|_| invalid())?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).map_err(|_| Failure::new(400, format!("'{segment}' isn't UTF-8")))
+}
+
+/// Compare tokens without giving away how much of them matched.
+fn same(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn api<'a>(config: &'a Config, path: &'a Path) -> Api<'a> {
+        let conn = Connection::new(&path.to_string_lossy()).unwrap();
+        conn.unlock().unwrap();
+        Api {
+            config,
+            conn,
+            database_path: path,
+            lock_timeout: None,
+            token: None,
+        }
+    }
+
+    #[test]
+    fn endpoints_read_and_change_counters() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("t.db");
+        let config = Config::default();
+        let api = api(&config, &path);
+
+        let created = api
+            .handle(
+                &Method::Post,
+                "/counters/builds",
+                None,
+                r##"{"count": 5, "step": 2, "template": "#{}"}"##,
+            )
+            .unwrap();
+        assert_eq!(created["count"], 5);
+        assert_eq!(created["template"], "#{}");
+
+        let added = api
+            .handle(&Method::Post, "/counters/builds/add", None, "")
+            .unwrap();
+        assert_eq!(added["count"], 7);
+        let added = api
+            .handle(
+                &Method::Post,
+                "/counters/builds/add",
+                None,
+                r#"{"amount": -10}"#,
+            )
+            .unwrap();
+        assert_eq!(added["value"], "-3");
+
+        let got = api
+            .handle(&Method::Get, "/counters/builds", None, "")
+            .unwrap();
+        assert_eq!(got["count"], -3);
+        let all = api.handle(&Method::Get, "/counters?x=1", None, "").unwrap();
+        assert_eq!(all.as_array().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn names_are_percent_decoded() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("t.db");
+        let config = Config::default();
+        let api = api(&config, &path);
+
+        let added = api
+            .handle(&Method::Post, "/counters/caf%C3%A9/add", None, "")
+            .unwrap();
+        assert_eq!(added["name"], "café");
+        api.handle(&Method::Post, "/counters/my%20build", None, "")
+            .unwrap();
+        let got = api
+            .handle(&Method::Get, "/counters/my%20build", None, "")
+            .unwrap();
+        assert_eq!(got["name"], "my build");
+
+        for bad in [
+            "/counters/%FF",
+            "/counters/%2",
+            "/counters/%+1",
+            "/counters/%zz",
+        ] {
+            let failure = api.handle(&Method::Get, bad, None, "").unwrap_err();
+            assert_eq!(failure.status, 400, "{bad}");
+        }
+    }
+
+    #[test]
+    fn failures_have_statuses() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("t.db");
+        let config = Config::default();
+        let mut api = api(&config, &path);
+        let status = |result: Result<Value, Failure>| result.unwrap_err().status;
+
+        assert_eq!(
+            status(api.handle(&Method::Get, "/counters/nope", None, "")),
+            404
+        );
+        assert_eq!(
+            status(api.handle(&Method::Get, "/elsewhere", None, "")),
+            404
+        );
+        assert_eq!(
+            status(api.handle(&Method::Delete, "/counters/a", None, "")),
+            405
+        );
+        assert_eq!(
+            status(api.handle(&Method::Get, "/counters/a/add", None, "")),
+            405
+        );
+        assert_eq!(
+            status(api.handle(&Method::Post, "/counters/a", None, r#"{"colour": 1}"#)),
+            400
+        );
+        assert_eq!(
+            status(api.handle(&Method::Post, "/counters/a", None, r#"{"template": "{"}"#)),
+            400
+        );
+
+        // Bad input is the client's fault, a busy or broken database isn't
+        let busy = Connection::new(&path.to_string_lossy()).unwrap();
+        api.lock_timeout = Some(Duration::ZERO);
+        assert_eq!(status(api.handle(&Method::Get, "/counters", None, "")), 503);
+        drop(busy);
+        api.lock_timeout = None;
+        let broken = anyhow::Error::from(sqlite::Error {
+            code: Some(1),
+            message: None,
+        });
+        assert_eq!(Failure::from(broken).status, 500);
+
+        api.token = Some("secret");
+        assert_eq!(status(api.handle(&Method::Get, "/counters", None, "")), 401);
+        assert_eq!(
+            status(api.handle(&Method::Get, "/counters", Some("Bearer wrong"), "")),
+            401
+        );
+        assert!(api
+            .handle(&Method::Get, "/counters", Some("Bearer secret"), "")
+            .is_ok());
+    }
+}
@@ -1,3 +1,17 @@
+pub mod app;
+pub mod cli;
+pub mod clock;
+pub mod config;
+pub mod daemon;
 pub mod database;
+pub mod gauge;
+pub mod git;
+pub mod history;
+pub mod hooks;
+pub mod http;
 pub mod models;
 pub mod template;
+pub mod top;
+pub mod version;
+
+pub use app::run;
@@ -1,4 +1,8 @@
-use sqlite::{ConnectionThreadSafe, State};
+use crate::gauge::Gauge;
+use crate::template::{Formula, Value};
+use crate::version::{Calver, Semver};
+use chrono::NaiveDate;
+use sqlite::{ConnectionThreadSafe, State, Statement};
 
 #[derive(Debug)]
 pub struct Counter {
@@ -6,6 +10,74 @@ pub struct Counter {
     pub count: i64,
     pub step: i64,
     pub template: String,
+    pub kind: Kind,
+    /// Scope whose value `count` and `kind` hold, `None` for the counter's own
+    pub scope: Option<String>,
+}
+
+/// What a counter holds, a plain count or something richer.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum Kind {
+    #[default]
+    Counter,
+    Semver(Semver),
+    Calver(Calver),
+    Gauge(Gauge),
+    /// Worked out from other counters whenever it's read
+    Formula(Formula),
+}
+
+impl Kind {
+    /// Name stored in the `kind` column.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Kind::Counter => "counter",
+            Kind::Semver(_) => "semver",
+            Kind::Calver(_) => "calver",
+            Kind::Gauge(_) => "gauge",
+            Kind::Formula(_) => "formula",
+        }
+    }
+
+    /// Contents of the `state` column.
+    fn state(&self) -> String {
+        match self {
+            Kind::Counter => String::new(),
+            Kind::Semver(version) => version.to_string(),
+            Kind::Calver(version) => format!("{} {}", version.date, version.scheme),
+            Kind::Gauge(gauge) => gauge.to_string(),
+            Kind::Formula(formula) => formula.to_string(),
+        }
+    }
+
+    fn load(kind: &str, state: &str) -> sqlite::Result<Kind> {
+        let invalid = |message: String| sqlite::Error {
+            code: None,
+            message: Some(message),
+        };
+        match kind {
+            "counter" => Ok(Kind::Counter),
+            "semver" => Ok(Kind::Semver(
+                state.parse().map_err(|e| invalid(format!("{e}")))?,
+            )),
+            "calver" => {
+                let (date, scheme) = state
+                    .split_once(' ')
+                    .ok_or_else(|| invalid(format!("invalid calver state '{state}'")))?;
+                Ok(Kind::Calver(Calver {
+                    scheme: scheme.parse().map_err(|e| invalid(format!("{e}")))?,
+                    date: date.parse().map_err(|e| invalid(format!("{e}")))?,
+                }))
+            }
+            "gauge" => Ok(Kind::Gauge(
+                state.parse().map_err(|e| invalid(format!("{e}")))?,
+            )),
+            "formula" => Ok(Kind::Formula(
+                state.parse().map_err(|e| invalid(format!("{e}")))?,
+            )),
+            _ => Err(invalid(format!("unknown counter kind '{kind}'"))),
+        }
+    }
 }
 
 impl Counter {
@@ -15,6 +87,55 @@ impl Counter {
             count: 0,
             step: 1,
             template: String::from("{}"),
+            kind: Kind::Counter,
+            scope: None,
+        }
+    }
+
+    /// The counter's value as text, what `{}` renders. A formula can't be
+    /// worked out without the database, see `template::display`.
+    pub fn display(&self) -> String {
+        match &self.kind {
+            Kind::Counter | Kind::Gauge(_) => self.count.to_string(),
+            Kind::Semver(version) => version.to_string(),
+            Kind::Calver(version) => version.render(self.count),
+            Kind::Formula(formula) => formula.to_string(),
+        }
+    }
+
+    /// A numeric part of the counter by name, `{app.major}` in templates.
+    pub fn field(&self, name: &str) -> Option<Value> {
+        match (&self.kind, name) {
+            (_, "step") => Some(Value::Int(self.step)),
+            (Kind::Counter, "count") => Some(Value::Int(self.count)),
+            (Kind::Semver(version), name) => version.field(name).map(Value::Int),
+            (Kind::Calver(_), "n") => Some(Value::Int(self.count)),
+            (Kind::Calver(version), name) => version.field(name).map(Value::Int),
+            (Kind::Gauge(_), "last") => Some(Value::Int(self.count)),
+            (Kind::Gauge(gauge), name) => gauge.field(name),
+            _ => None,
+        }
+    }
+
+    /// Give the counter a new count, which a gauge adds to its statistics.
+    pub fn set_count(&mut self, value: i64) {
+        if let Kind::Gauge(gauge) = &mut self.kind {
+            gauge.record(value);
+        }
+        self.count = value;
+    }
+
+    /// Add `amount` to the count on `today`. A calendar version entering a
+    /// new period adds it to 0 instead, starting its release number over.
+    pub fn add(&mut self, amount: i64, today: NaiveDate) {
+        let new_period = match &mut self.kind {
+            Kind::Calver(version) => version.advance(today),
+            _ => false,
+        };
+        if new_period {
+            self.count = amount;
+        } else {
+            self.count += amount;
         }
     }
 
@@ -39,64 +160,147 @@ impl Counter {
     }
 
     pub fn insert(&self, conn: &ConnectionThreadSafe) -> sqlite::Result<()> {
-        let mut stmt =
-            conn.prepare("INSERT INTO counters (name, count, step, template) VALUES (?, ?, ?, ?)")?;
+        let mut stmt = conn.prepare(
+            "INSERT INTO counters (name, count, step, template, kind, state)
+             VALUES (?, ?, ?, ?, ?, ?)",
+        )?;
         stmt.bind((1, self.name.as_str()))?;
         stmt.bind((2, self.count))?;
         stmt.bind((3, self.step))?;
         stmt.bind((4, self.template.as_str()))?;
+        stmt.bind((5, self.kind.name()))?;
+        stmt.bind((6, self.kind.state().as_str()))?;
         stmt.next()?;
 
         Ok(())
     }
 
     pub fn delete(conn: &ConnectionThreadSafe, name: &str) -> sqlite::Result<()> {
-        let mut stmt = conn.prepare("DELETE FROM counters WHERE name = ?")?;
-        stmt.bind((1, name))?;
-        stmt.next()?;
+        for table in ["counters", "scoped_counts", "history", "hooks"] {
+            let mut stmt = conn.prepare(format!("DELETE FROM {table} WHERE name = ?"))?;
+            stmt.bind((1, name))?;
+            stmt.next()?;
+        }
+        Ok(())
+    }
+
+    /// Forget the counter's value in one scope, leaving the counter itself.
+    pub fn delete_scope(
+        conn: &ConnectionThreadSafe,
+        name: &str,
+        scope: &str,
+    ) -> sqlite::Result<()> {
+        for table in ["scoped_counts", "history"] {
+            let mut stmt =
+                conn.prepare(format!("DELETE FROM {table} WHERE name = ? AND scope = ?"))?;
+            stmt.bind((1, name))?;
+            stmt.bind((2, scope))?;
+            stmt.next()?;
+        }
         Ok(())
     }
 
+    /// Switch to the counter's value in `scope`. A scope starts counting
+    /// from 0, versions start from the counter's own.
+    pub fn with_scope(
+        mut self,
+        conn: &ConnectionThreadSafe,
+        scope: &str,
+    ) -> sqlite::Result<Counter> {
+        let mut stmt = conn
+            .prepare("SELECT count, kind, state FROM scoped_counts WHERE name = ? AND scope = ?")?;
+        stmt.bind((1, self.name.as_str()))?;
+        stmt.bind((2, scope))?;
+        if let State::Row = stmt.next()? {
+            self.count = stmt.read::<i64, usize>(0)?;
+            self.kind = Kind::load(
+                &stmt.read::<String, usize>(1)?,
+                &stmt.read::<String, usize>(2)?,
+            )?;
+        } else {
+            self.count = 0;
+        }
+        self.scope = Some(scope.to_string());
+        Ok(self)
+    }
+
     pub fn get(conn: &ConnectionThreadSafe, name: &str) -> sqlite::Result<Option<Counter>> {
-        let mut stmt =
-            conn.prepare("SELECT name, count, step, template FROM counters WHERE name = ?")?;
+        let mut stmt = conn.prepare(
+            "SELECT name, count, step, template, kind, state FROM counters WHERE name = ?",
+        )?;
         stmt.bind((1, name))?;
 
         if let State::Row = stmt.next()? {
-            Ok(Some(Counter {
-                name: stmt.read::<String, _>(0)?.to_string(),
-                count: stmt.read::<i64, _>(1)?,
-                step: stmt.read::<i64, _>(2)?,
-                template: stmt.read::<String, _>(3)?.to_string(),
-            }))
+            Ok(Some(Counter::read(&stmt)?))
         } else {
             Ok(None)
         }
     }
 
     pub fn get_all(conn: &ConnectionThreadSafe) -> sqlite::Result<Vec<Counter>> {
-        let mut stmt = conn.prepare("SELECT name, count, step, template FROM counters")?;
+        let mut stmt =
+            conn.prepare("SELECT name, count, step, template, kind, state FROM counters")?;
         let mut counters = Vec::new();
 
         while let State::Row = stmt.next()? {
-            counters.push(Counter {
-                name: stmt.read::<String, usize>(0)?,
-                count: stmt.read::<i64, usize>(1)?,
-                step: stmt.read::<i64, usize>(2)?,
-                template: stmt.read::<String, usize>(3)?,
-            });
+            counters.push(Counter::read(&stmt)?);
         }
 
         Ok(counters)
     }
 
+    /// Build a counter from the current row of a `SELECT` of every column.
+    fn read(stmt: &Statement) -> sqlite::Result<Counter> {
+        Ok(Counter {
+            name: stmt.read::<String, usize>(0)?,
+            count: stmt.read::<i64, usize>(1)?,
+            step: stmt.read::<i64, usize>(2)?,
+            template: stmt.read::<String, usize>(3)?,
+            kind: Kind::load(
+                &stmt.read::<String, usize>(4)?,
+                &stmt.read::<String, usize>(5)?,
+            )?,
+            scope: None,
+        })
+    }
+
     pub fn update(&self, conn: &ConnectionThreadSafe) -> sqlite::Result<()> {
-        let mut stmt =
-            conn.prepare("UPDATE counters SET count = ?, step = ?, template = ? WHERE name = ?")?;
+        if let Some(scope) = &self.scope {
+            return self.update_scope(conn, scope);
+        }
+        let mut stmt = conn.prepare(
+            "UPDATE counters SET count = ?, step = ?, template = ?, kind = ?, state = ?
+             WHERE name = ?",
+        )?;
         stmt.bind((1, self.count))?;
         stmt.bind((2, self.step))?;
         stmt.bind((3, self.template.as_str()))?;
-        stmt.bind((4, self.name.as_str()))?;
+        stmt.bind((4, self.kind.name()))?;
+        stmt.bind((5, self.kind.state().as_str()))?;
+        stmt.bind((6, self.name.as_str()))?;
+        stmt.next()?;
+        Ok(())
+    }
+
+    /// Store the value in `scope`, and the settings every scope shares on
+    /// the counter itself.
+    fn update_scope(&self, conn: &ConnectionThreadSafe, scope: &str) -> sqlite::Result<()> {
+        let mut stmt = conn.prepare("UPDATE counters SET step = ?, template = ? WHERE name = ?")?;
+        stmt.bind((1, self.step))?;
+        stmt.bind((2, self.template.as_str()))?;
+        stmt.bind((3, self.name.as_str()))?;
+        stmt.next()?;
+
+        let mut stmt = conn.prepare(
+            "INSERT INTO scoped_counts (name, scope, count, kind, state) VALUES (?, ?, ?, ?, ?)
+             ON CONFLICT (name, scope) DO UPDATE
+             SET count = excluded.count, kind = excluded.kind, state = excluded.state",
+        )?;
+        stmt.bind((1, self.name.as_str()))?;
+        stmt.bind((2, scope))?;
+        stmt.bind((3, self.count))?;
+        stmt.bind((4, self.kind.name()))?;
+        stmt.bind((5, self.kind.state().as_str()))?;
         stmt.next()?;
         Ok(())
     }
@@ -132,6 +336,8 @@ mod tests {
             count: 7,
             step: 2,
             template: "x-{}".into(),
+            kind: Kind::Counter,
+            scope: None,
         };
         c.insert(conn.get()).unwrap();
 
@@ -162,6 +368,105 @@ mod tests {
         assert_eq!(loaded.step, 5);
     }
 
+    #[test]
+    fn semver_kind_round_trips() {
+        let (_dir, conn) = fresh_db();
+        let mut c = Counter::new("app");
+        c.kind = Kind::Semver("1.2.3-rc.1".parse().unwrap());
+        c.insert(conn.get()).unwrap();
+
+        let mut loaded = Counter::get(conn.get(), "app").unwrap().unwrap();
+        assert_eq!(loaded.kind, c.kind);
+        assert_eq!(loaded.display(), "1.2.3-rc.1");
+        assert_eq!(loaded.field("minor"), Some(Value::Int(2)));
+
+        loaded.kind = Kind::Counter;
+        loaded.update(conn.get()).unwrap();
+        let loaded = Counter::get(conn.get(), "app").unwrap().unwrap();
+        assert_eq!(loaded.kind, Kind::Counter);
+        assert_eq!(loaded.field("minor"), None);
+    }
+
+    #[test]
+    fn calver_kind_round_trips_and_resets() {
+        let (_dir, conn) = fresh_db();
+        let mut c = Counter::new("release");
+        c.kind = Kind::Calver(Calver {
+            scheme: "YYYY.0M.N".parse().unwrap(),
+            date: "2026-09-30".parse().unwrap(),
+        });
+        c.count = 3;
+        c.insert(conn.get()).unwrap();
+
+        let mut loaded = Counter::get(conn.get(), "release").unwrap().unwrap();
+        assert_eq!(loaded.kind, c.kind);
+        assert_eq!(loaded.display(), "2026.09.3");
+
+        loaded.add(1, "2026-09-30".parse().unwrap());
+        assert_eq!(loaded.display(), "2026.09.4");
+        loaded.add(1, "2026-10-01".parse().unwrap());
+        assert_eq!(loaded.display(), "2026.10.1");
+        assert_eq!(loaded.field("n"), Some(Value::Int(1)));
+        assert_eq!(loaded.field("month"), Some(Value::Int(10)));
+        loaded.add(0, "2026-11-02".parse().unwrap());
+        assert_eq!(loaded.display(), "2026.11.0");
+        loaded.add(3, "2026-12-01".parse().unwrap());
+        assert_eq!(loaded.display(), "2026.12.3");
+    }
+
+    #[test]
+    fn gauge_kind_records_sets() {
+        let (_dir, conn) = fresh_db();
+        let mut c = Counter::new("queue");
+        c.kind = Kind::Gauge(Gauge::new(0));
+        c.insert(conn.get()).unwrap();
+
+        let mut loaded = Counter::get(conn.get(), "queue").unwrap().unwrap();
+        loaded.set_count(8);
+        loaded.set_count(4);
+        loaded.update(conn.get()).unwrap();
+
+        let loaded = Counter::get(conn.get(), "queue").unwrap().unwrap();
+        assert_eq!(loaded.display(), "4");
+        assert_eq!(loaded.field("last"), Some(Value::Int(4)));
+        assert_eq!(loaded.field("max"), Some(Value::Int(8)));
+        assert_eq!(loaded.field("avg"), Some(Value::Float(4.0)));
+    }
+
+    #[test]
+    fn scopes_keep_separate_values() {
+        let (_dir, conn) = fresh_db();
+        let mut build = Counter::new("build");
+        build.count = 10;
+        build.insert(conn.get()).unwrap();
+
+        let mut main = Counter::get(conn.get(), "build")
+            .unwrap()
+            .unwrap()
+            .with_scope(conn.get(), "repo:main")
+            .unwrap();
+        assert_eq!(main.count, 0);
+        main.count = 3;
+        main.step = 2;
+        main.update(conn.get()).unwrap();
+
+        let reload = |scope: &str| {
+            Counter::get(conn.get(), "build")
+                .unwrap()
+                .unwrap()
+                .with_scope(conn.get(), scope)
+                .unwrap()
+        };
+        assert_eq!(reload("repo:main").count, 3);
+        assert_eq!(reload("repo:topic").count, 0);
+        let unscoped = Counter::get(conn.get(), "build").unwrap().unwrap();
+        assert_eq!((unscoped.count, unscoped.step), (10, 2));
+
+        Counter::delete_scope(conn.get(), "build", "repo:main").unwrap();
+        assert_eq!(reload("repo:main").count, 0);
+        assert_eq!(Counter::get_all(conn.get()).unwrap().len(), 2);
+    }
+
     #[test]
     fn delete_removes_row() {
         let (_dir, conn) = fresh_db();
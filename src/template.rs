@@ -1,17 +1,164 @@
+use crate::clock;
 use crate::database::Connection;
-use crate::models::Counter;
-use anyhow::{anyhow, Result};
-use regex::Regex;
-use std::collections::HashSet;
-use std::sync::LazyLock;
+use crate::history;
+use crate::models::{Counter, Kind};
+use anyhow::{anyhow, bail, Context, Result};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, LazyLock, Mutex};
 
-static TEMPLATE_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\{(.*?)\}").unwrap());
+mod ast;
+mod expr;
+mod format;
+mod formula;
+mod graph;
+mod named;
+mod parse;
+
+pub use ast::{Builtin, Dependency, Format, Named, Node, Template};
+pub use expr::{Expr, Value};
+pub use format::Spec;
+pub use formula::Formula;
+pub use graph::{Graph, Problem};
+
+/// Name that refers to the count of the counter being rendered, always,
+/// so a counter called that can't be read by name.
+const SELF_NAME: &str = "count";
+
+/// How many of the latest changes `{name:spark}` draws.
+const SPARK_POINTS: usize = 16;
+
+/// Turns a value into text for a tag such as `{build:roman}`.
+pub type Formatter = fn(Value) -> Result<String>;
+
+/// Formatters usable by name after the `:` in a tag. Names here win over
+/// format specs, so they mustn't be valid specs themselves.
+const FORMATTERS: &[(&str, Formatter)] = &[
+    ("roman", named::roman),
+    ("words", named::words),
+    ("ordinal", named::ordinal),
+    ("si", named::si),
+    ("bytes", named::bytes),
+    ("duration", named::duration),
+];
+
+/// Look up a named formatter.
+pub fn formatter(name: &str) -> Option<Named> {
+    FORMATTERS
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|&(name, format)| Named { name, format })
+}
+
+/// How many parsed templates are kept, so a long-running `serve` fed ever
+/// new templates doesn't grow without bound.
+const CACHE_SIZE: usize = 256;
+
+/// Parsed templates keyed by their source, most renders walk the same few
+/// templates over and over.
+static CACHE: LazyLock<Mutex<Cache>> = LazyLock::new(Default::default);
+
+/// The latest `CACHE_SIZE` templates used, each with when it last was.
+#[derive(Default)]
+struct Cache {
+    templates: HashMap<String, (Arc<Template>, u64)>,
+    clock: u64,
+}
+
+impl Cache {
+    fn get(&mut self, source: &str) -> Option<Arc<Template>> {
+        self.clock += 1;
+        let (template, used) = self.templates.get_mut(source)?;
+        *used = self.clock;
+        Some(Arc::clone(template))
+    }
+
+    /// Keep `template`, forgetting the least recently used one if full.
+    fn insert(&mut self, source: &str, template: Arc<Template>) {
+        if self.templates.len() >= CACHE_SIZE && !self.templates.contains_key(source) {
+            let oldest = self
+                .templates
+                .iter()
+                .min_by_key(|(_, (_, used))| *used)
+                .map(|(source, _)| source.clone());
+            if let Some(oldest) = oldest {
+                self.templates.remove(&oldest);
+            }
+        }
+        self.clock += 1;
+        self.templates
+            .insert(source.to_string(), (template, self.clock));
+    }
+}
+
+/// Check that a template parses, so mistakes surface when it's set rather
+/// than when it's rendered.
+pub fn validate(template: &str) -> Result<()> {
+    parsed(template).map(|_| ())
+}
+
+/// Parse a template, reusing an earlier parse of the same source.
+pub fn parsed(template: &str) -> Result<Arc<Template>> {
+    if let Some(parsed) = CACHE.lock().unwrap().get(template) {
+        return Ok(parsed);
+    }
+    let parsed = Arc::new(Template::parse(template)?);
+    CACHE.lock().unwrap().insert(template, Arc::clone(&parsed));
+    Ok(parsed)
+}
+
+/// Whether rendering the counter reads the environment or the clock, in
+/// its own template or one it references.
+pub fn uses_environment(conn: &Connection, counter: &Counter) -> Result<bool> {
+    let mut seen = HashSet::from([counter.name.clone()]);
+    let mut templates = vec![counter.template.clone()];
+    while let Some(template) = templates.pop() {
+        let parsed = parsed(&template)?;
+        if parsed.reads_environment() {
+            return Ok(true);
+        }
+        for (name, dep) in parsed.dependencies() {
+            if dep == Dependency::Reference && seen.insert(name.to_string()) {
+                if let Some(counter) = Counter::get(conn.get(), name)? {
+                    templates.push(counter.template);
+                }
+            }
+        }
+    }
+    Ok(false)
+}
 
 pub fn render(conn: &Connection, name: &str) -> Result<String> {
     let mut visited = HashSet::new();
     render_inner(conn, name, &mut visited)
 }
 
+/// Render the template of a counter that's already loaded, with its value
+/// as loaded rather than as stored under its name.
+pub fn render_counter(conn: &Connection, counter: &Counter) -> Result<String> {
+    let mut visited = HashSet::new();
+    visited.insert(counter.name.clone());
+    render_loaded(conn, counter, &mut visited)
+}
+
+/// Render a template that isn't stored on any counter. `counter`, if any,
+/// is what `{}` and `{counter_name}` refer to.
+pub fn render_template(
+    conn: &Connection,
+    template: &str,
+    counter: Option<&Counter>,
+) -> Result<String> {
+    let parsed = parsed(template)?;
+    let mut rendered = String::with_capacity(template.len());
+    render_nodes(
+        conn,
+        counter,
+        &parsed.nodes,
+        &mut HashSet::new(),
+        &mut rendered,
+    )?;
+    Ok(rendered)
+}
+
 fn render_inner(conn: &Connection, name: &str, visited: &mut HashSet<String>) -> Result<String> {
     if !visited.insert(name.to_string()) {
         return Err(anyhow!(
@@ -20,21 +167,233 @@ fn render_inner(conn: &Connection, name: &str, visited: &mut HashSet<String>) ->
         ));
     }
 
-    let counter = match Counter::get(conn.get(), name)? {
-        Some(c) => c,
-        None => return Err(anyhow!("Unable to find counter for templating")),
+    let counter = lookup(conn, name)?;
+    let rendered = render_loaded(conn, &counter, visited)?;
+
+    visited.remove(name);
+    Ok(rendered)
+}
+
+fn render_loaded(
+    conn: &Connection,
+    counter: &Counter,
+    visited: &mut HashSet<String>,
+) -> Result<String> {
+    let template = parsed(&counter.template)
+        .with_context(|| format!("invalid template of counter '{}'", counter.name))?;
+
+    let mut rendered = String::with_capacity(counter.template.len());
+    render_nodes(conn, Some(counter), &template.nodes, visited, &mut rendered)?;
+    Ok(rendered)
+}
+
+fn render_nodes(
+    conn: &Connection,
+    counter: Option<&Counter>,
+    nodes: &[Node],
+    visited: &mut HashSet<String>,
+    out: &mut String,
+) -> Result<()> {
+    for node in nodes {
+        let context = |source: &str| match counter {
+            Some(counter) => format!(
+                "failed to render '{{{source}}}' in template of counter '{}'",
+                counter.name
+            ),
+            None => format!("failed to render '{{{source}}}'"),
+        };
+        match node {
+            Node::Text(text) => out.push_str(text),
+            // `{app.major}` is a field when there's no counter by that name
+            Node::Reference(name)
+                if Counter::get(conn.get(), name)?.is_none() && name.contains('.') =>
+            {
+                out.push_str(&number(conn, counter, name, &mut HashSet::new())?.to_string())
+            }
+            Node::Reference(name) => out.push_str(&render_inner(conn, name, visited)?),
+            Node::Value {
+                source,
+                expr: Expr::Name(name),
+                format: None,
+            } if name == SELF_NAME => {
+                let this = this(counter).with_context(|| context(source))?;
+                out.push_str(&display(conn, this).with_context(|| context(source))?)
+            }
+            Node::Value {
+                source,
+                expr,
+                format,
+            } => {
+                let value = value(conn, counter, expr).with_context(|| context(source))?;
+                let formatted = match format {
+                    Some(Format::Spec(spec)) => spec.format_value(value),
+                    Some(Format::Named(named)) => (named.format)(value),
+                    None => Ok(value.to_string()),
+                };
+                let formatted = formatted.with_context(|| context(source))?;
+                out.push_str(&formatted);
+            }
+            Node::If {
+                source,
+                cond,
+                then,
+                otherwise,
+            } => {
+                // Only the branch taken is evaluated
+                let taken = value(conn, counter, cond)
+                    .with_context(|| context(source))?
+                    .is_truthy();
+                let branch = if taken { then } else { otherwise };
+                render_nodes(conn, counter, branch, visited, out)?;
+            }
+            Node::Plural {
+                source,
+                expr,
+                one,
+                many,
+            } => {
+                let value = value(conn, counter, expr).with_context(|| context(source))?;
+                let is_one = matches!(value, Value::Int(1 | -1))
+                    || matches!(value, Value::Float(f) if f.abs() == 1.0);
+                out.push_str(if is_one { one } else { many });
+            }
+            Node::Spark { source, name } => {
+                out.push_str(&spark(conn, counter, name).with_context(|| context(source))?)
+            }
+            Node::Builtin { source, builtin } => {
+                out.push_str(&builtin_value(counter, builtin).with_context(|| context(source))?)
+            }
+        }
+    }
+    Ok(())
+}
+
+fn builtin_value(counter: Option<&Counter>, builtin: &Builtin) -> Result<String> {
+    let text = |s: &str, spec: &Option<Spec>| match spec {
+        Some(spec) => spec.pad(s, format::Align::Left),
+        None => s.to_string(),
     };
+    Ok(match builtin {
+        Builtin::Date(format) | Builtin::Now(format) => clock::now()?.format(format).to_string(),
+        Builtin::Env(var) => std::env::var(var)
+            .with_context(|| format!("environment variable '{var}' is not usable"))?,
+        Builtin::Hostname(spec) => text(&gethostname::gethostname().to_string_lossy(), spec),
+        Builtin::CounterName(spec) => text(&this(counter)?.name, spec),
+    })
+}
 
-    let mut rendered = counter.template.replace("{}", &counter.count.to_string());
+/// Sparkline of the counter a `{name:spark}` placeholder names.
+fn spark(conn: &Connection, counter: Option<&Counter>, name: &str) -> Result<String> {
+    let found;
+    let target = if name == SELF_NAME {
+        this(counter)?
+    } else {
+        found = lookup(conn, name)?;
+        &found
+    };
+    sparkline(conn, target)
+}
 
-    for cap in TEMPLATE_RE.captures_iter(&rendered.clone()) {
-        rendered = rendered.replace(&cap[0], "{}");
-        let sub_template = render_inner(conn, &cap[1], visited)?;
-        rendered = rendered.replace("{}", &sub_template);
+/// Sparkline of a counter's latest counts, or of its count alone when it
+/// has no history yet.
+pub fn sparkline(conn: &Connection, counter: &Counter) -> Result<String> {
+    let entries = history::recent(
+        conn.get(),
+        &counter.name,
+        counter.scope.as_deref(),
+        SPARK_POINTS,
+    )?;
+    let mut counts: Vec<i64> = entries.iter().map(|e| e.count).collect();
+    if counts.is_empty() {
+        counts.push(counter.count);
     }
+    Ok(history::sparkline(&counts))
+}
 
-    visited.remove(name);
-    Ok(rendered)
+/// Evaluate an expression against the counter being rendered and the
+/// counts of the counters it names.
+fn value(conn: &Connection, counter: Option<&Counter>, expr: &Expr) -> Result<Value> {
+    expr.eval(&mut |name| number(conn, counter, name, &mut HashSet::new()))
+}
+
+/// The counter's value as text, what `{}` renders. Formulas are worked out
+/// here since that takes the database.
+pub fn display(conn: &Connection, counter: &Counter) -> Result<String> {
+    match counter.kind {
+        Kind::Formula(_) => Ok(count(conn, counter, &mut HashSet::new())?.to_string()),
+        _ => Ok(counter.display()),
+    }
+}
+
+/// What a name stands for in an expression, a counter's count or a field
+/// of one as in `app.major`. `visited` holds the formulas being evaluated.
+fn number(
+    conn: &Connection,
+    counter: Option<&Counter>,
+    name: &str,
+    visited: &mut HashSet<String>,
+) -> Result<Value> {
+    if name == SELF_NAME {
+        return count(conn, this(counter)?, visited);
+    }
+    if let Some(found) = Counter::get(conn.get(), name)? {
+        return count(conn, &found, visited);
+    }
+    if let Some((base, field)) = name.rsplit_once('.') {
+        let field_of = |found: &Counter| {
+            found
+                .field(field)
+                .ok_or_else(|| anyhow!("counter '{}' has no field '{field}'", found.name))
+        };
+        if base == SELF_NAME {
+            return field_of(this(counter)?);
+        }
+        if let Some(found) = Counter::get(conn.get(), base)? {
+            return field_of(&found);
+        }
+    }
+    Err(anyhow!("Unable to find counter '{name}' for templating"))
+}
+
+/// A counter as a single number, which plain counters, gauges and
+/// formulas are.
+fn count(conn: &Connection, counter: &Counter, visited: &mut HashSet<String>) -> Result<Value> {
+    match &counter.kind {
+        Kind::Counter | Kind::Gauge(_) => Ok(Value::Int(counter.count)),
+        Kind::Formula(formula) => {
+            if !visited.insert(counter.name.clone()) {
+                bail!(
+                    "formula cycle detected involving counter '{}'",
+                    counter.name
+                );
+            }
+            let value = formula
+                .expr
+                .eval(&mut |name| number(conn, Some(counter), name, visited));
+            visited.remove(&counter.name);
+            value
+        }
+        _ => Err(anyhow!(
+            "counter '{}' is a {} counter, use one of its fields such as '{}.{}'",
+            counter.name,
+            counter.kind.name(),
+            counter.name,
+            match counter.kind {
+                Kind::Calver(_) => "n",
+                _ => "major",
+            }
+        )),
+    }
+}
+
+/// The counter being rendered, which ad-hoc templates may not have.
+fn this(counter: Option<&Counter>) -> Result<&Counter> {
+    counter.ok_or_else(|| anyhow!("no counter is being rendered, name one or refer to it by name"))
+}
+
+fn lookup(conn: &Connection, name: &str) -> Result<Counter> {
+    Counter::get(conn.get(), name)?
+        .ok_or_else(|| anyhow!("Unable to find counter '{name}' for templating"))
 }
 
 #[cfg(test)]
@@ -57,6 +416,8 @@ mod tests {
             count,
             step: 1,
             template: template.into(),
+            kind: Default::default(),
+            scope: None,
         };
         c.insert(conn.get()).unwrap();
     }
@@ -106,6 +467,215 @@ mod tests {
         assert!(err.contains("cycle"), "got: {err}");
     }
 
+    #[test]
+    fn formats_own_count() {
+        let (_dir, conn) = fresh_db();
+        put(&conn, "a", 42, "v1.0.{:04}");
+        assert_eq!(render(&conn, "a").unwrap(), "v1.0.0042");
+        put(&conn, "b", 255, "{:x}|{:#b}|{:>5}|{}");
+        assert_eq!(render(&conn, "b").unwrap(), "ff|0b11111111|  255|255");
+    }
+
+    #[test]
+    fn formats_referenced_count() {
+        let (_dir, conn) = fresh_db();
+        put(&conn, "build", 7, "b{}");
+        put(&conn, "big", 1234567, "{}");
+        put(&conn, "release", 0, "{build:04}-{build}-{big:,}");
+        assert_eq!(render(&conn, "release").unwrap(), "0007-b7-1,234,567");
+    }
+
+    #[test]
+    fn formatted_self_reference_is_not_a_cycle() {
+        let (_dir, conn) = fresh_db();
+        put(&conn, "a", 3, "{a:02}");
+        assert_eq!(render(&conn, "a").unwrap(), "03");
+    }
+
+    #[test]
+    fn rendered_reference_containing_braces_is_not_substituted() {
+        let (_dir, conn) = fresh_db();
+        put(&conn, "inner", 0, "{inner:x}");
+        put(&conn, "outer", 5, "{inner}-{}");
+        assert_eq!(render(&conn, "outer").unwrap(), "0-5");
+    }
+
+    #[test]
+    fn invalid_spec_errors() {
+        let (_dir, conn) = fresh_db();
+        put(&conn, "a", 3, "{:q}");
+        let err = format!("{:#}", render(&conn, "a").unwrap_err());
+        assert!(err.contains("invalid format spec"), "got: {err}");
+    }
+
+    #[test]
+    fn evaluates_arithmetic() {
+        let (_dir, conn) = fresh_db();
+        put(&conn, "hits", 3, "{}");
+        put(&conn, "total", 4, "{}");
+        put(&conn, "build", 42, "{}");
+        put(
+            &conn,
+            "a",
+            5,
+            "{count * 2} {build + 1000} {hits / total * 100:.1}%",
+        );
+        assert_eq!(render(&conn, "a").unwrap(), "10 1042 75.0%");
+    }
+
+    #[test]
+    fn arithmetic_on_names_with_dashes() {
+        let (_dir, conn) = fresh_db();
+        put(&conn, "new-counter", 10, "n{}");
+        put(
+            &conn,
+            "a",
+            0,
+            "{new-counter} {new-counter - 1} {(new-counter):03}",
+        );
+        assert_eq!(render(&conn, "a").unwrap(), "n10 9 010");
+    }
+
+    #[test]
+    fn arithmetic_errors_are_reported() {
+        let (_dir, conn) = fresh_db();
+        put(&conn, "zero", 0, "{}");
+        put(&conn, "a", 1, "{count / zero}");
+        put(&conn, "b", i64::MAX, "{count + 1}");
+        put(&conn, "c", 1, "{ghost * 2}");
+        put(&conn, "d", 1, "{count +}");
+
+        let err = |name| format!("{:#}", render(&conn, name).unwrap_err());
+        assert!(err("a").contains("division by zero"), "{}", err("a"));
+        assert!(err("b").contains("overflow"), "{}", err("b"));
+        assert!(err("c").contains("'ghost'"), "{}", err("c"));
+        assert!(err("d").contains("template of counter 'd'"), "{}", err("d"));
+    }
+
+    #[test]
+    fn renders_conditionals() {
+        let (_dir, conn) = fresh_db();
+        put(&conn, "errors", 2, "{}");
+        put(&conn, "warnings", 0, "{}");
+        put(&conn, "a", 0, "{if errors > 0}FAIL{else}ok{end}");
+        put(
+            &conn,
+            "b",
+            0,
+            "{if warnings}warn{end}|{if !warnings}quiet{end}",
+        );
+        put(
+            &conn,
+            "c",
+            0,
+            "{if errors}{if warnings}both{else}errors only{end}{end}",
+        );
+        assert_eq!(render(&conn, "a").unwrap(), "FAIL");
+        assert_eq!(render(&conn, "b").unwrap(), "|quiet");
+        assert_eq!(render(&conn, "c").unwrap(), "errors only");
+    }
+
+    #[test]
+    fn untaken_branches_are_not_evaluated() {
+        let (_dir, conn) = fresh_db();
+        put(&conn, "a", 0, "{if count}{ghost}{count / 0}{else}none{end}");
+        assert_eq!(render(&conn, "a").unwrap(), "none");
+    }
+
+    #[test]
+    fn cache_forgets_the_least_recently_used() {
+        let mut cache = Cache::default();
+        let template = Arc::new(Template::parse("{}").unwrap());
+        for i in 0..CACHE_SIZE {
+            cache.insert(&i.to_string(), Arc::clone(&template));
+        }
+        assert!(cache.get("0").is_some());
+        cache.insert("new", Arc::clone(&template));
+        assert_eq!(cache.templates.len(), CACHE_SIZE);
+        assert!(cache.get("0").is_some());
+        assert!(cache.get("1").is_none());
+        assert!(cache.get("new").is_some());
+    }
+
+    #[test]
+    fn count_is_always_the_counter_being_rendered() {
+        let (_dir, conn) = fresh_db();
+        put(&conn, "a", 1, "{count} {count + 1} {}");
+        assert_eq!(render(&conn, "a").unwrap(), "1 2 1");
+        // A counter by that name changes nothing
+        put(&conn, "count", 10, "#{}");
+        assert_eq!(render(&conn, "a").unwrap(), "1 2 1");
+    }
+
+    #[test]
+    fn renders_plurals() {
+        let (_dir, conn) = fresh_db();
+        put(&conn, "one", 1, r#"{count} {plural count "file" "files"}"#);
+        put(&conn, "many", 3, r#"{count} {plural count "file" "files"}"#);
+        put(&conn, "zero", 0, r#"{count} {plural count "file" "files"}"#);
+        assert_eq!(render(&conn, "one").unwrap(), "1 file");
+        assert_eq!(render(&conn, "many").unwrap(), "3 files");
+        assert_eq!(render(&conn, "zero").unwrap(), "0 files");
+    }
+
+    #[test]
+    fn unbalanced_conditionals_error() {
+        let (_dir, conn) = fresh_db();
+        put(&conn, "a", 0, "{if count}x");
+        put(&conn, "b", 0, "x{end}");
+        put(&conn, "c", 0, "{else}");
+        put(&conn, "d", 0, "{if count}{else}{else}{end}");
+        put(&conn, "e", 0, r#"{plural count "file"}"#);
+        for name in ["a", "b", "c", "d", "e"] {
+            assert!(render(&conn, name).is_err(), "{name} should fail");
+        }
+    }
+
+    #[test]
+    fn doubled_braces_render_literally() {
+        let (_dir, conn) = fresh_db();
+        put(&conn, "a", 4, r#"{{"count": {}}}"#);
+        put(&conn, "b", 4, "${{HOME}}/{}");
+        assert_eq!(render(&conn, "a").unwrap(), r#"{"count": 4}"#);
+        assert_eq!(render(&conn, "b").unwrap(), "${HOME}/4");
+    }
+
+    #[test]
+    fn validate_accepts_good_templates() {
+        for template in [
+            "{}",
+            "v{build:04}",
+            "{{json}}",
+            "{if a > 1}{if b}x{else}y{end}{end}",
+            r#"{plural count "a" "b"}"#,
+        ] {
+            assert!(validate(template).is_ok(), "{template}");
+        }
+    }
+
+    #[test]
+    fn validate_reports_bad_templates() {
+        let err = |t| format!("{:#}", validate(t).unwrap_err());
+        assert!(err("${HOME").contains("unclosed '{'"), "{}", err("${HOME"));
+        assert!(err("a}").contains("unmatched '}'"), "{}", err("a}"));
+        assert!(
+            err("{count +}").contains("'{count +}'"),
+            "{}",
+            err("{count +}")
+        );
+        assert!(
+            err("{:q}").contains("invalid format spec"),
+            "{}",
+            err("{:q}")
+        );
+        assert!(
+            err("{if count}").contains("without {end}"),
+            "{}",
+            err("{if count}")
+        );
+        assert!(err("{end}").contains("without {if}"), "{}", err("{end}"));
+    }
+
     #[test]
     fn sibling_references_are_not_cycles() {
         let (_dir, conn) = fresh_db();
@@ -113,4 +683,217 @@ mod tests {
         put(&conn, "root", 0, "{leaf}-{leaf}");
         assert_eq!(render(&conn, "root").unwrap(), "9-9");
     }
+
+    #[test]
+    fn rendered_braces_are_not_reparsed() {
+        let (_dir, conn) = fresh_db();
+        put(&conn, "inner", 1, "{{}}{{count}}{{outer}}");
+        put(&conn, "outer", 5, "{inner}{}");
+        assert_eq!(render(&conn, "outer").unwrap(), "{}{count}{outer}5");
+    }
+
+    #[test]
+    fn same_reference_in_different_forms() {
+        let (_dir, conn) = fresh_db();
+        put(&conn, "b", 7, "b{}");
+        put(&conn, "a", 0, "{b}|{ b }|{b:03}|{b + 0}|{b}");
+        assert_eq!(render(&conn, "a").unwrap(), "b7|b7|007|7|b7");
+    }
+
+    #[test]
+    fn text_that_looks_like_syntax_is_kept() {
+        let (_dir, conn) = fresh_db();
+        put(&conn, "a", 2, "if count > 0: {if count}yes{end} else end");
+        put(&conn, "b", 2, r#"{plural count "{a}" "{{}}"}"#);
+        put(&conn, "c", 2, "ünïcödé {} 🎉");
+        assert_eq!(render(&conn, "a").unwrap(), "if count > 0: yes else end");
+        assert_eq!(render(&conn, "b").unwrap(), "{{}}");
+        assert_eq!(render(&conn, "c").unwrap(), "ünïcödé 2 🎉");
+    }
+
+    #[test]
+    fn renders_builtins() {
+        let (_dir, conn) = fresh_db();
+        put(
+            &conn,
+            "release",
+            42,
+            "{counter_name}-{:04} {counter_name:*<9}|",
+        );
+        put(&conn, "host", 0, "{hostname}");
+        put(&conn, "path", 0, "{env:PATH}");
+        assert_eq!(render(&conn, "release").unwrap(), "release-0042 release**|");
+        assert_eq!(
+            render(&conn, "host").unwrap(),
+            gethostname::gethostname().to_string_lossy()
+        );
+        assert_eq!(
+            render(&conn, "path").unwrap(),
+            std::env::var("PATH").unwrap()
+        );
+    }
+
+    #[test]
+    fn renders_current_date() {
+        let (_dir, conn) = fresh_db();
+        put(&conn, "a", 0, "{date:%Y}");
+        let year = render(&conn, "a").unwrap();
+        assert_eq!(year.len(), 4);
+        assert!(year.parse::<u32>().unwrap() >= 2024, "{year}");
+    }
+
+    #[test]
+    fn missing_env_var_errors() {
+        let (_dir, conn) = fresh_db();
+        put(&conn, "a", 0, "{env:TALLY_SURELY_UNSET_VARIABLE}");
+        let err = format!("{:#}", render(&conn, "a").unwrap_err());
+        assert!(err.contains("TALLY_SURELY_UNSET_VARIABLE"), "{err}");
+    }
+
+    #[test]
+    fn named_formatters() {
+        let (_dir, conn) = fresh_db();
+        put(&conn, "size", 1536, "{}");
+        put(&conn, "uptime", 3725, "{}");
+        put(
+            &conn,
+            "a",
+            14,
+            "{:roman} {:words} {:ordinal} {size:bytes} {size:si} {uptime:duration}",
+        );
+        assert_eq!(
+            render(&conn, "a").unwrap(),
+            "XIV fourteen 14th 1.5 KiB 1.5k 1h 2m 5s"
+        );
+        put(&conn, "b", 0, "{:roman}");
+        let err = format!("{:#}", render(&conn, "b").unwrap_err());
+        assert!(err.contains("roman numerals"), "{err}");
+    }
+
+    #[test]
+    fn every_formatter_name_is_unique_and_not_a_spec() {
+        for (i, (name, _)) in FORMATTERS.iter().enumerate() {
+            assert!(name.parse::<Spec>().is_err(), "{name} parses as a spec");
+            assert!(FORMATTERS[..i].iter().all(|(n, _)| n != name), "{name}");
+        }
+    }
+
+    #[test]
+    fn renders_ad_hoc_templates() {
+        let (_dir, conn) = fresh_db();
+        put(&conn, "build", 7, "b{}");
+        put(&conn, "patch", 3, "{}");
+        let build = lookup(&conn, "build").unwrap();
+        assert_eq!(
+            render_template(&conn, "{build}-{patch:03}", None).unwrap(),
+            "b7-003"
+        );
+        assert_eq!(
+            render_template(&conn, "{counter_name}={:02}", Some(&build)).unwrap(),
+            "build=07"
+        );
+        // The stored template is left alone
+        assert_eq!(lookup(&conn, "build").unwrap().template, "b{}");
+    }
+
+    #[test]
+    fn ad_hoc_templates_without_a_counter() {
+        let (_dir, conn) = fresh_db();
+        let err = format!("{:#}", render_template(&conn, "{}", None).unwrap_err());
+        assert!(err.contains("no counter is being rendered"), "{err}");
+        assert!(render_template(&conn, "{counter_name}", None).is_err());
+        assert!(render_template(&conn, "{", None).is_err());
+    }
+
+    #[test]
+    fn renders_version_counters_and_fields() {
+        let (_dir, conn) = fresh_db();
+        let mut app = Counter::new("app");
+        app.kind = Kind::Semver("1.4.2-rc.1".parse().unwrap());
+        app.template = "v{}".into();
+        app.insert(conn.get()).unwrap();
+        put(&conn, "a.b", 5, "{}");
+        put(
+            &conn,
+            "tag",
+            0,
+            "{app}|{app.major}.{app.minor + 1}|{app.patch:03}|{a.b}",
+        );
+        put(&conn, "own", 0, "{count.step}");
+
+        assert_eq!(render(&conn, "app").unwrap(), "v1.4.2-rc.1");
+        assert_eq!(render(&conn, "tag").unwrap(), "v1.4.2-rc.1|1.5|002|5");
+        assert_eq!(render(&conn, "own").unwrap(), "1");
+
+        let err = |t| format!("{:#}", render_template(&conn, t, None).unwrap_err());
+        assert!(
+            err("{app + 1}").contains("use one of its fields"),
+            "{}",
+            err("{app + 1}")
+        );
+        assert!(
+            err("{app.build}").contains("no field 'build'"),
+            "{}",
+            err("{app.build}")
+        );
+    }
+
+    #[test]
+    fn parsed_templates_are_cached() {
+        let first = parsed("cached {count:04}").unwrap();
+        let second = parsed("cached {count:04}").unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+        assert!(parsed("cached {").is_err());
+        assert!(parsed("cached {").is_err());
+    }
+
+    #[test]
+    fn cached_template_renders_each_counter() {
+        let (_dir, conn) = fresh_db();
+        put(&conn, "a", 1, "#{:02}");
+        put(&conn, "b", 2, "#{:02}");
+        assert_eq!(render(&conn, "a").unwrap(), "#01");
+        assert_eq!(render(&conn, "b").unwrap(), "#02");
+    }
+
+    fn put_formula(conn: &Connection, name: &str, formula: &str, template: &str) {
+        let mut c = Counter::new(name);
+        c.template = template.into();
+        c.kind = Kind::Formula(formula.parse().unwrap());
+        c.insert(conn.get()).unwrap();
+    }
+
+    #[test]
+    fn formulas_are_worked_out_when_read() {
+        let (_dir, conn) = fresh_db();
+        put(&conn, "passed", 3, "{}");
+        put(&conn, "total", 4, "{}");
+        put_formula(&conn, "ratio", "{passed} / {total}", "{}");
+        put_formula(&conn, "percent", "ratio * 100", "{:.1}%");
+        assert_eq!(render(&conn, "ratio").unwrap(), "0.75");
+        assert_eq!(render(&conn, "percent").unwrap(), "75.0%");
+        assert_eq!(render_template(&conn, "{ratio + 1}", None).unwrap(), "1.75");
+    }
+
+    #[test]
+    fn formulas_referring_to_themselves_fail() {
+        let (_dir, conn) = fresh_db();
+        put_formula(&conn, "a", "b + 1", "{}");
+        put_formula(&conn, "b", "a + 1", "{}");
+        let err = format!("{:#}", render(&conn, "a").unwrap_err());
+        assert!(err.contains("formula cycle detected"), "{err}");
+    }
+
+    #[test]
+    fn long_formula_chains_are_fine() {
+        let (_dir, conn) = fresh_db();
+        put(&conn, "f0", 1, "{}");
+        for i in 1..=40 {
+            put_formula(&conn, &format!("f{i}"), &format!("f{} + 1", i - 1), "{}");
+        }
+        // Reading the same formula twice isn't a loop either
+        put_formula(&conn, "twice", "f40 + f40", "{}");
+        assert_eq!(render(&conn, "f40").unwrap(), "41");
+        assert_eq!(render(&conn, "twice").unwrap(), "82");
+    }
 }
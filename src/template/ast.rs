@@ -0,0 +1,471 @@
+use super::expr::{self, Expr};
+use super::format::Spec;
+use super::parse::{self, Piece};
+use super::SELF_NAME;
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::format::{Item, StrftimeItems};
+use std::collections::BTreeMap;
+
+/// How deep `{if}` blocks may nest, rendering walks them recursively.
+const MAX_DEPTH: usize = 64;
+
+/// A parsed template, built once and rendered any number of times.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    pub nodes: Vec<Node>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Node {
+    Text(String),
+    /// `{name}`, rendered with the referenced counter's own template.
+    Reference(String),
+    /// Any other expression, evaluated and formatted as a number.
+    Value {
+        source: String,
+        expr: Expr,
+        format: Option<Format>,
+    },
+    /// `{if errors > 0}...{else}...{end}`
+    If {
+        source: String,
+        cond: Expr,
+        then: Vec<Node>,
+        otherwise: Vec<Node>,
+    },
+    /// `{plural count "file" "files"}`
+    Plural {
+        source: String,
+        expr: Expr,
+        one: String,
+        many: String,
+    },
+    /// `{build:spark}`, a sparkline of the counter's recent counts.
+    Spark {
+        source: String,
+        name: String,
+    },
+    /// `{date:%Y%m%d}`, `{env:USER}` and friends, which don't read counters.
+    Builtin {
+        source: String,
+        builtin: Builtin,
+    },
+}
+
+/// Placeholders filled in from outside the database. Their names take
+/// precedence over counters with the same name.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Builtin {
+    /// Current date, or any strftime format, `{date}` or `{date:%Y%m%d}`
+    Date(String),
+    /// Current time, `{now}` or `{now:%H:%M}`
+    Now(String),
+    /// `{env:USER}`, fails when the variable isn't set
+    Env(String),
+    Hostname(Option<Spec>),
+    /// Name of the counter being rendered
+    CounterName(Option<Spec>),
+}
+
+impl Builtin {
+    const DATE_FORMAT: &str = "%Y-%m-%d";
+    const TIME_FORMAT: &str = "%H:%M:%S";
+
+    /// Recognise a built-in by the text before and after its `:`.
+    fn parse(name: &str, arg: Option<&str>) -> Result<Option<Builtin>> {
+        let spec = || arg.map(str::parse::<Spec>).transpose();
+        Ok(Some(match name {
+            "date" => Builtin::Date(strftime(arg.unwrap_or(Self::DATE_FORMAT))?),
+            "now" => Builtin::Now(strftime(arg.unwrap_or(Self::TIME_FORMAT))?),
+            "env" => match arg.map(str::trim) {
+                Some(var) if !var.is_empty() => Builtin::Env(var.to_string()),
+                _ => bail!("env needs a variable name, as in {{env:USER}}"),
+            },
+            "hostname" => Builtin::Hostname(spec()?),
+            "counter_name" => Builtin::CounterName(spec()?),
+            _ => return Ok(None),
+        }))
+    }
+}
+
+/// Check a strftime format up front, chrono panics on bad ones when rendering.
+fn strftime(format: &str) -> Result<String> {
+    if StrftimeItems::new(format).any(|item| item == Item::Error) {
+        bail!("invalid date format '{format}'");
+    }
+    Ok(format.to_string())
+}
+
+/// What follows the `:` in a value tag.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Format {
+    Spec(Spec),
+    /// `{build:roman}`
+    Named(Named),
+}
+
+impl Format {
+    fn parse(s: &str) -> Result<Format> {
+        match super::formatter(s.trim()) {
+            Some(named) => Ok(Format::Named(named)),
+            None => Ok(Format::Spec(s.parse()?)),
+        }
+    }
+}
+
+/// A formatter from the registry in `template.rs`.
+#[derive(Debug, Clone, Copy)]
+pub struct Named {
+    pub name: &'static str,
+    pub format: super::Formatter,
+}
+
+impl PartialEq for Named {
+    fn eq(&self, other: &Named) -> bool {
+        self.name == other.name
+    }
+}
+
+/// How a template uses another counter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Dependency {
+    /// Only its count, in an expression
+    Value,
+    /// Its whole rendered template, `{name}`
+    Reference,
+}
+
+/// An `{if}` whose `{end}` hasn't been reached yet.
+struct Open {
+    outer: Vec<Node>,
+    source: String,
+    cond: Expr,
+    then: Option<Vec<Node>>,
+}
+
+impl Template {
+    pub fn parse(template: &str) -> Result<Template> {
+        let mut nodes = Vec::new();
+        let mut open: Vec<Open> = Vec::new();
+
+        for piece in parse::lex(template)? {
+            let source = match piece {
+                Piece::Text(text) => {
+                    nodes.push(Node::Text(text));
+                    continue;
+                }
+                Piece::Tag(source) => source,
+            };
+            let tag = tag(source).with_context(|| format!("invalid tag '{{{source}}}'"))?;
+            match tag {
+                Tag::If(cond) => {
+                    if open.len() == MAX_DEPTH {
+                        bail!("{{if}} nested more than {MAX_DEPTH} deep");
+                    }
+                    open.push(Open {
+                        outer: std::mem::take(&mut nodes),
+                        source: source.to_string(),
+                        cond,
+                        then: None,
+                    });
+                }
+                Tag::Else => match open.last_mut() {
+                    Some(block) if block.then.is_none() => {
+                        block.then = Some(std::mem::take(&mut nodes));
+                    }
+                    Some(_) => bail!("second {{else}} for one {{if}}"),
+                    None => bail!("{{else}} without {{if}}"),
+                },
+                Tag::End => {
+                    let block = open
+                        .pop()
+                        .ok_or_else(|| anyhow!("{{end}} without {{if}}"))?;
+                    let body = std::mem::replace(&mut nodes, block.outer);
+                    let (then, otherwise) = match block.then {
+                        Some(then) => (then, body),
+                        None => (body, Vec::new()),
+                    };
+                    nodes.push(Node::If {
+                        source: block.source,
+                        cond: block.cond,
+                        then,
+                        otherwise,
+                    });
+                }
+                Tag::Node(node) => nodes.push(node),
+            }
+        }
+        if !open.is_empty() {
+            bail!("{{if}} without {{end}}");
+        }
+        Ok(Template { nodes })
+    }
+
+    /// Whether rendering reads the environment or the clock, so it may
+    /// come out differently in another process.
+    pub fn reads_environment(&self) -> bool {
+        fn any(nodes: &[Node]) -> bool {
+            nodes.iter().any(|node| match node {
+                Node::Builtin { builtin, .. } => matches!(
+                    builtin,
+                    Builtin::Date(_) | Builtin::Now(_) | Builtin::Env(_)
+                ),
+                Node::If {
+                    then, otherwise, ..
+                } => any(then) || any(otherwise),
+                _ => false,
+            })
+        }
+        any(&self.nodes)
+    }
+
+    /// Counters this template reads, other than the one rendering it.
+    pub fn dependencies(&self) -> BTreeMap<&str, Dependency> {
+        let mut deps = BTreeMap::new();
+        collect_dependencies(&self.nodes, &mut deps);
+        deps
+    }
+}
+
+fn collect_dependencies<'a>(nodes: &'a [Node], deps: &mut BTreeMap<&'a str, Dependency>) {
+    let values = |expr: &'a Expr, deps: &mut BTreeMap<&'a str, Dependency>| {
+        for name in expr.names() {
+            if name != SELF_NAME {
+                deps.entry(name).or_insert(Dependency::Value);
+            }
+        }
+    };
+    for node in nodes {
+        match node {
+            Node::Text(_) | Node::Builtin { .. } => {}
+            Node::Reference(name) => {
+                deps.insert(name, Dependency::Reference);
+            }
+            Node::Value { expr, .. } | Node::Plural { expr, .. } => values(expr, deps),
+            Node::Spark { name, .. } => {
+                if name != SELF_NAME {
+                    deps.entry(name).or_insert(Dependency::Value);
+                }
+            }
+            Node::If {
+                cond,
+                then,
+                otherwise,
+                ..
+            } => {
+                values(cond, deps);
+                collect_dependencies(then, deps);
+                collect_dependencies(otherwise, deps);
+            }
+        }
+    }
+}
+
+enum Tag {
+    If(Expr),
+    Else,
+    End,
+    Node(Node),
+}
+
+/// Split a leading keyword such as `if` off a tag's contents.
+fn keyword<'a>(source: &'a str, keyword: &str) -> Option<&'a str> {
+    let rest = source.strip_prefix(keyword)?;
+    rest.starts_with(char::is_whitespace).then_some(rest)
+}
+
+fn tag(raw: &str) -> Result<Tag> {
+    let source = raw.trim();
+    match source {
+        "else" => return Ok(Tag::Else),
+        "end" => return Ok(Tag::End),
+        _ => {}
+    }
+    if let Some(cond) = keyword(source, "if") {
+        return Ok(Tag::If(expr::parse(cond)?));
+    }
+    if let Some(args) = keyword(source, "plural") {
+        return match expr::parse_args(args)? {
+            (expr, strings) if strings.len() == 2 => {
+                let [one, many] = <[String; 2]>::try_from(strings).unwrap();
+                Ok(Tag::Node(Node::Plural {
+                    source: raw.to_string(),
+                    expr,
+                    one,
+                    many,
+                }))
+            }
+            _ => Err(anyhow!("plural takes a value and two strings")),
+        };
+    }
+
+    let (name, arg) = match source.split_once(':') {
+        Some((name, arg)) => (name.trim(), Some(arg)),
+        None => (source, None),
+    };
+    if let Some(builtin) = Builtin::parse(name, arg)? {
+        return Ok(Tag::Node(Node::Builtin {
+            source: raw.to_string(),
+            builtin,
+        }));
+    }
+
+    let expr = match name {
+        "" => Expr::Name(SELF_NAME.to_string()),
+        name => expr::parse(name)?,
+    };
+    if arg.map(str::trim) == Some("spark") {
+        let Expr::Name(name) = expr else {
+            bail!("spark draws the history of a counter, name one as in {{build:spark}}");
+        };
+        return Ok(Tag::Node(Node::Spark {
+            source: raw.to_string(),
+            name,
+        }));
+    }
+    let format = arg.map(Format::parse).transpose()?;
+    Ok(Tag::Node(match (expr, format) {
+        (Expr::Name(name), None) if name != SELF_NAME => Node::Reference(name),
+        (expr, format) => Node::Value {
+            source: raw.to_string(),
+            expr,
+            format,
+        },
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(t: &str) -> Vec<Node> {
+        Template::parse(t).unwrap().nodes
+    }
+
+    fn text(s: &str) -> Node {
+        Node::Text(s.to_string())
+    }
+
+    #[test]
+    fn parses_text_and_references() {
+        assert_eq!(
+            parse("[{inner}]"),
+            vec![text("["), Node::Reference("inner".into()), text("]")]
+        );
+        assert_eq!(parse(""), vec![]);
+    }
+
+    #[test]
+    fn self_and_formatted_tags_are_values() {
+        for t in ["{}", "{count}", "{ count }", "{inner:04}"] {
+            let nodes = parse(t);
+            assert!(matches!(nodes[..], [Node::Value { .. }]), "{t}: {nodes:?}");
+        }
+    }
+
+    #[test]
+    fn builds_nested_branches() {
+        let nodes = parse("{if a}{if b}x{else}y{end}{else}z{end}");
+        let [Node::If {
+            then, otherwise, ..
+        }] = &nodes[..]
+        else {
+            panic!("{nodes:?}");
+        };
+        assert_eq!(otherwise, &vec![text("z")]);
+        let [Node::If {
+            then, otherwise, ..
+        }] = &then[..]
+        else {
+            panic!("{then:?}");
+        };
+        assert_eq!((then, otherwise), (&vec![text("x")], &vec![text("y")]));
+    }
+
+    #[test]
+    fn keywords_need_a_separator() {
+        // `iffy` and `plurals` are counter names, not keywords
+        assert_eq!(parse("{iffy}"), vec![Node::Reference("iffy".into())]);
+        assert_eq!(parse("{plurals}"), vec![Node::Reference("plurals".into())]);
+    }
+
+    #[test]
+    fn lists_dependencies() {
+        let template = Template::parse(
+            "{a}{b:03}{if c > count}{a + d}{else}{plural e \"x\" \"y\"}{end}{date}",
+        )
+        .unwrap();
+        let deps: Vec<_> = template.dependencies().into_iter().collect();
+        assert_eq!(
+            deps,
+            vec![
+                ("a", Dependency::Reference),
+                ("b", Dependency::Value),
+                ("c", Dependency::Value),
+                ("d", Dependency::Value),
+                ("e", Dependency::Value),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_builtins() {
+        let builtin = |t| match &parse(t)[..] {
+            [Node::Builtin { builtin, .. }] => builtin.clone(),
+            nodes => panic!("{t}: {nodes:?}"),
+        };
+        assert_eq!(builtin("{date}"), Builtin::Date("%Y-%m-%d".into()));
+        assert_eq!(builtin("{date:%Y%m%d}"), Builtin::Date("%Y%m%d".into()));
+        assert_eq!(builtin("{ now:%H:%M}"), Builtin::Now("%H:%M".into()));
+        assert_eq!(builtin("{env:USER}"), Builtin::Env("USER".into()));
+        assert_eq!(builtin("{hostname}"), Builtin::Hostname(None));
+        assert_eq!(
+            builtin("{counter_name:>8}"),
+            Builtin::CounterName(Some(">8".parse().unwrap()))
+        );
+    }
+
+    #[test]
+    fn rejects_bad_builtins() {
+        for t in ["{env}", "{env:}", "{date:%Q}", "{counter_name:q}"] {
+            assert!(Template::parse(t).is_err(), "{t}");
+        }
+    }
+
+    #[test]
+    fn rejects_runaway_nesting() {
+        let deep = "{if 1}".repeat(MAX_DEPTH + 1) + &"{end}".repeat(MAX_DEPTH + 1);
+        let err = Template::parse(&deep).unwrap_err().to_string();
+        assert!(err.contains("nested"), "{err}");
+        let ok = "{if 1}".repeat(MAX_DEPTH) + &"{end}".repeat(MAX_DEPTH);
+        assert!(Template::parse(&ok).is_ok());
+    }
+
+    #[test]
+    fn rejects_unbalanced_blocks() {
+        for t in [
+            "{if a}",
+            "{end}",
+            "{else}",
+            "{if a}{else}{else}{end}",
+            "{if a}{end}{end}",
+        ] {
+            assert!(Template::parse(t).is_err(), "{t}");
+        }
+    }
+
+    #[test]
+    fn spark_takes_a_counter_name() {
+        assert_eq!(
+            parse("{build:spark}"),
+            vec![Node::Spark {
+                source: "build:spark".into(),
+                name: "build".into()
+            }]
+        );
+        assert!(matches!(parse("{:spark}")[..], [Node::Spark { .. }]));
+        assert!(Template::parse("{build + 1:spark}").is_err());
+        let t = Template::parse("{build:spark}").unwrap();
+        assert_eq!(t.dependencies().get("build"), Some(&Dependency::Value));
+    }
+}
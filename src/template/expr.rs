@@ -0,0 +1,545 @@
+use anyhow::{anyhow, bail, Result};
+use std::cmp::Ordering;
+use std::fmt;
+
+/// How deep parentheses and unary operators may nest before the expression
+/// is rejected, well short of exhausting the stack.
+const MAX_DEPTH: usize = 64;
+
+/// The result of evaluating an expression.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+    Int(i64),
+    Float(f64),
+}
+
+impl Value {
+    fn as_f64(self) -> f64 {
+        match self {
+            Value::Int(n) => n as f64,
+            Value::Float(f) => f,
+        }
+    }
+
+    /// Anything but zero is true, comparisons yield 1 or 0.
+    pub fn is_truthy(self) -> bool {
+        match self {
+            Value::Int(n) => n != 0,
+            Value::Float(f) => f != 0.0,
+        }
+    }
+
+    fn from_bool(b: bool) -> Value {
+        Value::Int(b as i64)
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Int(n) => write!(f, "{n}"),
+            Value::Float(x) => write!(f, "{x}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
+}
+
+impl BinOp {
+    fn from_symbol(symbol: &str) -> Option<BinOp> {
+        Some(match symbol {
+            "+" => BinOp::Add,
+            "-" => BinOp::Sub,
+            "*" => BinOp::Mul,
+            "/" => BinOp::Div,
+            "%" => BinOp::Rem,
+            "==" => BinOp::Eq,
+            "!=" => BinOp::Ne,
+            "<" => BinOp::Lt,
+            "<=" => BinOp::Le,
+            ">" => BinOp::Gt,
+            ">=" => BinOp::Ge,
+            "&&" => BinOp::And,
+            "||" => BinOp::Or,
+            _ => return None,
+        })
+    }
+
+    fn symbol(self) -> &'static str {
+        match self {
+            BinOp::Add => "+",
+            BinOp::Sub => "-",
+            BinOp::Mul => "*",
+            BinOp::Div => "/",
+            BinOp::Rem => "%",
+            BinOp::Eq => "==",
+            BinOp::Ne => "!=",
+            BinOp::Lt => "<",
+            BinOp::Le => "<=",
+            BinOp::Gt => ">",
+            BinOp::Ge => ">=",
+            BinOp::And => "&&",
+            BinOp::Or => "||",
+        }
+    }
+}
+
+/// Arithmetic and comparisons over numbers and counter names, e.g.
+/// `hits / total * 100` or `errors > 0 && !muted`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Number(Value),
+    Name(String),
+    Neg(Box<Expr>),
+    Not(Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    /// Evaluate the expression, `resolve` supplies the value of each name.
+    pub fn eval(&self, resolve: &mut dyn FnMut(&str) -> Result<Value>) -> Result<Value> {
+        match self {
+            Expr::Number(v) => Ok(*v),
+            Expr::Name(name) => resolve(name),
+            Expr::Neg(e) => match e.eval(resolve)? {
+                Value::Int(n) => n
+                    .checked_neg()
+                    .map(Value::Int)
+                    .ok_or_else(|| anyhow!("integer overflow negating {n}")),
+                Value::Float(f) => Ok(Value::Float(-f)),
+            },
+            Expr::Not(e) => Ok(Value::from_bool(!e.eval(resolve)?.is_truthy())),
+            // Short circuit so the untaken side may divide by zero
+            Expr::Binary(BinOp::And, lhs, rhs) => Ok(Value::from_bool(
+                lhs.eval(resolve)?.is_truthy() && rhs.eval(resolve)?.is_truthy(),
+            )),
+            Expr::Binary(BinOp::Or, lhs, rhs) => Ok(Value::from_bool(
+                lhs.eval(resolve)?.is_truthy() || rhs.eval(resolve)?.is_truthy(),
+            )),
+            Expr::Binary(op, lhs, rhs) => {
+                let lhs = lhs.eval(resolve)?;
+                let rhs = rhs.eval(resolve)?;
+                apply(*op, lhs, rhs)
+            }
+        }
+    }
+
+    /// Names referenced anywhere in the expression.
+    pub fn names(&self) -> Vec<&str> {
+        let mut names = Vec::new();
+        self.collect_names(&mut names);
+        names
+    }
+
+    fn collect_names<'a>(&'a self, names: &mut Vec<&'a str>) {
+        match self {
+            Expr::Number(_) => {}
+            Expr::Name(name) => names.push(name),
+            Expr::Neg(e) | Expr::Not(e) => e.collect_names(names),
+            Expr::Binary(_, lhs, rhs) => {
+                lhs.collect_names(names);
+                rhs.collect_names(names);
+            }
+        }
+    }
+}
+
+fn apply(op: BinOp, lhs: Value, rhs: Value) -> Result<Value> {
+    if matches!(op, BinOp::Div | BinOp::Rem) && !rhs.is_truthy() {
+        bail!("division by zero in {lhs} {} {rhs}", op.symbol());
+    }
+
+    let ordering = match (lhs, rhs) {
+        (Value::Int(l), Value::Int(r)) => Some(l.cmp(&r)),
+        (l, r) => l.as_f64().partial_cmp(&r.as_f64()),
+    };
+    let compare = |accept: fn(Ordering) -> bool| Value::from_bool(ordering.is_some_and(accept));
+
+    match (op, lhs, rhs) {
+        (BinOp::Eq, _, _) => Ok(compare(Ordering::is_eq)),
+        (BinOp::Ne, _, _) => Ok(Value::from_bool(ordering != Some(Ordering::Equal))),
+        (BinOp::Lt, _, _) => Ok(compare(Ordering::is_lt)),
+        (BinOp::Le, _, _) => Ok(compare(Ordering::is_le)),
+        (BinOp::Gt, _, _) => Ok(compare(Ordering::is_gt)),
+        (BinOp::Ge, _, _) => Ok(compare(Ordering::is_ge)),
+        // Division is always true division, 7 / 2 is 3.5
+        (BinOp::Div, l, r) => Ok(Value::Float(l.as_f64() / r.as_f64())),
+        (op, Value::Int(l), Value::Int(r)) => {
+            let result = match op {
+                BinOp::Add => l.checked_add(r),
+                BinOp::Sub => l.checked_sub(r),
+                BinOp::Mul => l.checked_mul(r),
+                BinOp::Rem => l.checked_rem(r),
+                _ => unreachable!(),
+            };
+            result
+                .map(Value::Int)
+                .ok_or_else(|| anyhow!("integer overflow in {l} {} {r}", op.symbol()))
+        }
+        (op, l, r) => {
+            let (l, r) = (l.as_f64(), r.as_f64());
+            Ok(Value::Float(match op {
+                BinOp::Add => l + r,
+                BinOp::Sub => l - r,
+                BinOp::Mul => l * r,
+                BinOp::Rem => l % r,
+                _ => unreachable!(),
+            }))
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(Value),
+    Name(String),
+    Str(String),
+    Op(&'static str),
+    Open,
+    Close,
+}
+
+/// Characters that make up names and numbers. Counter names commonly
+/// contain `-`, so subtraction next to a name needs surrounding spaces.
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '_' | '-' | '.')
+}
+
+/// Operators, longest first so `>=` wins over `>`.
+const OPERATORS: [&str; 14] = [
+    "==", "!=", "<=", ">=", "&&", "||", "<", ">", "!", "+", "-", "*", "/", "%",
+];
+
+fn tokenize(src: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut rest = src;
+    while let Some(c) = rest.chars().next() {
+        if c.is_whitespace() {
+            rest = &rest[c.len_utf8()..];
+        } else if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(*op)) {
+            tokens.push(Token::Op(op));
+            rest = &rest[op.len()..];
+        } else if c == '(' || c == ')' {
+            tokens.push(if c == '(' { Token::Open } else { Token::Close });
+            rest = &rest[1..];
+        } else if c == '"' {
+            let end = rest[1..]
+                .find('"')
+                .ok_or_else(|| anyhow!("unterminated string in '{src}'"))?;
+            tokens.push(Token::Str(rest[1..end + 1].to_string()));
+            rest = &rest[end + 2..];
+        } else if is_word(c) {
+            let end = rest.find(|c| !is_word(c)).unwrap_or(rest.len());
+            tokens.push(word(&rest[..end]));
+            rest = &rest[end..];
+        } else {
+            bail!("unexpected '{c}' in '{src}'");
+        }
+    }
+    Ok(tokens)
+}
+
+fn word(w: &str) -> Token {
+    if w.starts_with(|c: char| c.is_ascii_digit()) {
+        if let Ok(n) = w.parse::<i64>() {
+            return Token::Number(Value::Int(n));
+        }
+        if w.chars().all(|c| c.is_ascii_digit() || c == '.') {
+            if let Ok(f) = w.parse::<f64>() {
+                return Token::Number(Value::Float(f));
+            }
+        }
+    }
+    Token::Name(w.to_string())
+}
+
+/// Parse an expression such as `build + 1000`.
+pub fn parse(src: &str) -> Result<Expr> {
+    let (expr, strings) = parse_args(src)?;
+    if !strings.is_empty() {
+        bail!("unexpected string \"{}\" in '{src}'", strings[0]);
+    }
+    Ok(expr)
+}
+
+/// Parse an expression followed by string literals, the arguments of
+/// `plural count "file" "files"`.
+pub fn parse_args(src: &str) -> Result<(Expr, Vec<String>)> {
+    let tokens = tokenize(src)?;
+    if tokens.is_empty() {
+        bail!("empty expression");
+    }
+    let mut parser = Parser {
+        src,
+        tokens,
+        pos: 0,
+        depth: 0,
+    };
+    let expr = parser.expr()?;
+    let mut strings = Vec::new();
+    while let Some(token) = parser.peek() {
+        match token {
+            Token::Str(s) => strings.push(s.clone()),
+            token => bail!("unexpected {} in '{src}'", describe(token)),
+        }
+        parser.pos += 1;
+    }
+    Ok((expr, strings))
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Number(v) => format!("'{v}'"),
+        Token::Name(n) => format!("'{n}'"),
+        Token::Str(s) => format!("string \"{s}\""),
+        Token::Op(op) => format!("'{op}'"),
+        Token::Open => "'('".to_string(),
+        Token::Close => "')'".to_string(),
+    }
+}
+
+struct Parser<'a> {
+    src: &'a str,
+    tokens: Vec<Token>,
+    pos: usize,
+    /// Parentheses and unary operators currently open
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Result<Token> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .cloned()
+            .ok_or_else(|| anyhow!("unexpected end of '{}'", self.src))?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn eat_op(&mut self, ops: &[&str]) -> Option<&'static str> {
+        match self.peek() {
+            Some(Token::Op(op)) if ops.contains(op) => {
+                let op = *op;
+                self.pos += 1;
+                Some(op)
+            }
+            _ => None,
+        }
+    }
+
+    /// Parse a left associative chain of `ops` between `operand`s.
+    fn chain(&mut self, ops: &[&str], operand: fn(&mut Self) -> Result<Expr>) -> Result<Expr> {
+        let mut lhs = operand(self)?;
+        while let Some(op) = self.eat_op(ops) {
+            let rhs = operand(self)?;
+            let op = BinOp::from_symbol(op).unwrap();
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    // expr := and ('||' and)*
+    fn expr(&mut self) -> Result<Expr> {
+        self.chain(&["||"], Self::and)
+    }
+
+    // and := comparison ('&&' comparison)*
+    fn and(&mut self) -> Result<Expr> {
+        self.chain(&["&&"], Self::comparison)
+    }
+
+    // comparison := sum (('==' | '!=' | '<' | '<=' | '>' | '>=') sum)?
+    fn comparison(&mut self) -> Result<Expr> {
+        let lhs = self.sum()?;
+        match self.eat_op(&["==", "!=", "<", "<=", ">", ">="]) {
+            Some(op) => {
+                let rhs = self.sum()?;
+                let op = BinOp::from_symbol(op).unwrap();
+                Ok(Expr::Binary(op, Box::new(lhs), Box::new(rhs)))
+            }
+            None => Ok(lhs),
+        }
+    }
+
+    // sum := term (('+' | '-') term)*
+    fn sum(&mut self) -> Result<Expr> {
+        self.chain(&["+", "-"], Self::term)
+    }
+
+    // term := unary (('*' | '/' | '%') unary)*
+    fn term(&mut self) -> Result<Expr> {
+        self.chain(&["*", "/", "%"], Self::unary)
+    }
+
+    /// Parse with `f` one level deeper, failing past `MAX_DEPTH`.
+    fn nested(&mut self, f: fn(&mut Self) -> Result<Expr>) -> Result<Expr> {
+        if self.depth == MAX_DEPTH {
+            bail!("expression nests more than {MAX_DEPTH} deep");
+        }
+        self.depth += 1;
+        let expr = f(self);
+        self.depth -= 1;
+        expr
+    }
+
+    // unary := ('-' | '!') unary | primary
+    fn unary(&mut self) -> Result<Expr> {
+        match self.eat_op(&["-", "!"]) {
+            Some("-") => Ok(Expr::Neg(Box::new(self.nested(Self::unary)?))),
+            Some(_) => Ok(Expr::Not(Box::new(self.nested(Self::unary)?))),
+            None => self.primary(),
+        }
+    }
+
+    // primary := number | name | '(' expr ')'
+    fn primary(&mut self) -> Result<Expr> {
+        match self.next()? {
+            Token::Number(v) => Ok(Expr::Number(v)),
+            Token::Name(n) => Ok(Expr::Name(n)),
+            Token::Open => {
+                let expr = self.nested(Self::expr)?;
+                match self.next()? {
+                    Token::Close => Ok(expr),
+                    token => bail!(
+                        "expected ')' but found {} in '{}'",
+                        describe(&token),
+                        self.src
+                    ),
+                }
+            }
+            token => bail!("unexpected {} in '{}'", describe(&token), self.src),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(src: &str) -> Result<Value> {
+        parse(src)?.eval(&mut |name| match name {
+            "hits" => Ok(Value::Int(3)),
+            "total" => Ok(Value::Int(4)),
+            "new-counter" => Ok(Value::Int(10)),
+            "zero" => Ok(Value::Int(0)),
+            "max" => Ok(Value::Int(i64::MAX)),
+            _ => Err(anyhow!("unknown counter '{name}'")),
+        })
+    }
+
+    #[test]
+    fn deep_nesting_is_rejected() {
+        let ok = "(".repeat(MAX_DEPTH) + "1" + &")".repeat(MAX_DEPTH);
+        assert_eq!(eval(&ok).unwrap(), Value::Int(1));
+        let deep = "(".repeat(20_000) + "1" + &")".repeat(20_000);
+        let e = eval(&deep).unwrap_err();
+        assert!(e.to_string().contains("nests more than"), "{e}");
+        assert!(eval(&("-".repeat(20_000) + "1")).is_err());
+        assert!(eval(&("!".repeat(20_000) + "1")).is_err());
+    }
+
+    #[test]
+    fn precedence_and_parentheses() {
+        assert_eq!(eval("1 + 2 * 3").unwrap(), Value::Int(7));
+        assert_eq!(eval("(1 + 2) * 3").unwrap(), Value::Int(9));
+        assert_eq!(eval("10 - 4 - 3").unwrap(), Value::Int(3));
+        assert_eq!(eval("-2 * -3").unwrap(), Value::Int(6));
+        assert_eq!(eval("7 % 4").unwrap(), Value::Int(3));
+    }
+
+    #[test]
+    fn division_is_true_division() {
+        assert_eq!(eval("hits / total * 100").unwrap(), Value::Float(75.0));
+        assert_eq!(eval("7 / 2").unwrap(), Value::Float(3.5));
+        assert_eq!(eval("1.5 + 1").unwrap(), Value::Float(2.5));
+    }
+
+    #[test]
+    fn names_with_dashes() {
+        assert_eq!(eval("new-counter + 1").unwrap(), Value::Int(11));
+        assert_eq!(eval("new-counter - 1").unwrap(), Value::Int(9));
+        assert_eq!(
+            parse("a.b").unwrap().names(),
+            vec!["a.b"],
+            "dots stay part of the name"
+        );
+    }
+
+    #[test]
+    fn unknown_names_error() {
+        let err = eval("ghost + 1").unwrap_err().to_string();
+        assert!(err.contains("unknown counter 'ghost'"), "got: {err}");
+    }
+
+    #[test]
+    fn division_by_zero_errors() {
+        let err = eval("hits / zero").unwrap_err().to_string();
+        assert!(err.contains("division by zero"), "got: {err}");
+        assert!(eval("hits % zero").is_err());
+    }
+
+    #[test]
+    fn overflow_errors() {
+        let err = eval("max + 1").unwrap_err().to_string();
+        assert!(err.contains("overflow"), "got: {err}");
+        assert!(eval("max * 2").is_err());
+        assert!(eval("-(-max - 1)").is_err());
+    }
+
+    #[test]
+    fn comparisons_and_logic() {
+        assert_eq!(eval("hits > 2").unwrap(), Value::Int(1));
+        assert_eq!(eval("hits >= 4").unwrap(), Value::Int(0));
+        assert_eq!(eval("hits + 1 == total").unwrap(), Value::Int(1));
+        assert_eq!(eval("hits != 3").unwrap(), Value::Int(0));
+        assert_eq!(eval("1.5 < 2").unwrap(), Value::Int(1));
+        assert_eq!(eval("hits > 0 && total < 10").unwrap(), Value::Int(1));
+        assert_eq!(eval("zero || !zero").unwrap(), Value::Int(1));
+        assert_eq!(eval("!(hits > 0)").unwrap(), Value::Int(0));
+    }
+
+    #[test]
+    fn logic_short_circuits() {
+        assert_eq!(eval("zero && hits / zero").unwrap(), Value::Int(0));
+        assert_eq!(eval("hits || hits / zero").unwrap(), Value::Int(1));
+    }
+
+    #[test]
+    fn string_arguments() {
+        let (expr, strings) = parse_args(r#"count "file" "two words""#).unwrap();
+        assert_eq!(expr, Expr::Name("count".into()));
+        assert_eq!(strings, vec!["file", "two words"]);
+        assert!(parse(r#"count "file""#).is_err());
+        assert!(parse_args(r#"count "open"#).is_err());
+    }
+
+    #[test]
+    fn syntax_errors() {
+        assert!(parse("").is_err());
+        assert!(parse("1 +").is_err());
+        assert!(parse("(1 + 2").is_err());
+        assert!(parse("1 2").is_err());
+        assert!(parse("a $ b").is_err());
+        assert!(parse("1 < 2 < 3").is_err());
+    }
+}
@@ -0,0 +1,338 @@
+use super::expr::Value;
+use anyhow::{anyhow, bail, Result};
+use std::str::FromStr;
+
+/// A parsed format spec, the part after `:` in `{build:04}`.
+///
+/// Follows Rust's `[[fill]align][sign][#][0][width][,][.precision][type]`,
+/// with `,` added for thousands separators.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Spec {
+    pub fill: char,
+    pub align: Option<Align>,
+    pub plus: bool,
+    pub alternate: bool,
+    pub zero: bool,
+    pub width: usize,
+    pub grouping: bool,
+    pub precision: Option<usize>,
+    pub radix: Radix,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Radix {
+    Decimal,
+    LowerHex,
+    UpperHex,
+    Octal,
+    Binary,
+}
+
+/// Largest width or precision a spec may ask for, which keeps a template
+/// from padding its output to gigabytes.
+const MAX_SIZE: usize = 1024;
+
+/// Parse a width or precision, refusing ones over `MAX_SIZE`.
+fn size(digits: &str, what: &str, spec: &str) -> Result<usize> {
+    match digits.parse() {
+        Ok(n) if n <= MAX_SIZE => Ok(n),
+        _ => bail!("{what} in format spec '{spec}' is over {MAX_SIZE}"),
+    }
+}
+
+impl Default for Spec {
+    fn default() -> Spec {
+        Spec {
+            fill: ' ',
+            align: None,
+            plus: false,
+            alternate: false,
+            zero: false,
+            width: 0,
+            grouping: false,
+            precision: None,
+            radix: Radix::Decimal,
+        }
+    }
+}
+
+fn align(c: char) -> Option<Align> {
+    match c {
+        '<' => Some(Align::Left),
+        '^' => Some(Align::Center),
+        '>' => Some(Align::Right),
+        _ => None,
+    }
+}
+
+impl FromStr for Spec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Spec> {
+        let mut spec = Spec::default();
+        let mut rest = s;
+
+        // [[fill]align]
+        let mut chars = rest.chars();
+        let first = chars.next();
+        let second = chars.next();
+        if let Some(a) = second.and_then(align) {
+            spec.fill = first.unwrap();
+            spec.align = Some(a);
+            rest = &rest[first.unwrap().len_utf8() + 1..];
+        } else if let Some(a) = first.and_then(align) {
+            spec.align = Some(a);
+            rest = &rest[1..];
+        }
+
+        if let Some(r) = rest.strip_prefix('+') {
+            spec.plus = true;
+            rest = r;
+        }
+        if let Some(r) = rest.strip_prefix('#') {
+            spec.alternate = true;
+            rest = r;
+        }
+        if let Some(r) = rest.strip_prefix('0') {
+            spec.zero = true;
+            rest = r;
+        }
+
+        let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        if digits > 0 {
+            spec.width = size(&rest[..digits], "width", s)?;
+            rest = &rest[digits..];
+        }
+
+        if let Some(r) = rest.strip_prefix(',') {
+            spec.grouping = true;
+            rest = r;
+        }
+
+        if let Some(r) = rest.strip_prefix('.') {
+            let digits = r.len() - r.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+            if digits == 0 {
+                bail!("missing precision in format spec '{s}'");
+            }
+            spec.precision = Some(size(&r[..digits], "precision", s)?);
+            rest = &r[digits..];
+        }
+
+        spec.radix = match rest {
+            "" => Radix::Decimal,
+            "x" => Radix::LowerHex,
+            "X" => Radix::UpperHex,
+            "o" => Radix::Octal,
+            "b" => Radix::Binary,
+            _ => return Err(anyhow!("invalid format spec '{s}'")),
+        };
+        Ok(spec)
+    }
+}
+
+impl Spec {
+    /// Format the result of an expression, integers with a precision are
+    /// shown as decimals.
+    pub fn format_value(&self, value: Value) -> Result<String> {
+        match (value, self.precision) {
+            (Value::Int(n), None) => Ok(self.format_int(n)),
+            (Value::Int(n), Some(_)) => self.format_float(n as f64),
+            (Value::Float(f), _) => self.format_float(f),
+        }
+    }
+
+    fn format_float(&self, f: f64) -> Result<String> {
+        if self.radix != Radix::Decimal {
+            bail!("cannot format {f} in a radix other than 10");
+        }
+        let digits = match self.precision {
+            Some(precision) => format!("{:.precision$}", f.abs()),
+            None => f.abs().to_string(),
+        };
+        let digits = match digits.split_once('.') {
+            Some((int, frac)) if self.grouping => {
+                format!("{}.{frac}", group(int, self.radix))
+            }
+            _ if self.grouping => group(&digits, self.radix),
+            _ => digits,
+        };
+        let sign = if f.is_sign_negative() && f != 0.0 {
+            "-"
+        } else if self.plus {
+            "+"
+        } else {
+            ""
+        };
+        Ok(self.pad_number(sign, "", &digits))
+    }
+
+    /// Format an integer according to the spec.
+    pub fn format_int(&self, n: i64) -> String {
+        let magnitude = n.unsigned_abs();
+        let (digits, prefix) = match self.radix {
+            Radix::Decimal => (magnitude.to_string(), ""),
+            Radix::LowerHex => (format!("{magnitude:x}"), "0x"),
+            Radix::UpperHex => (format!("{magnitude:X}"), "0x"),
+            Radix::Octal => (format!("{magnitude:o}"), "0o"),
+            Radix::Binary => (format!("{magnitude:b}"), "0b"),
+        };
+        let digits = if self.grouping {
+            group(&digits, self.radix)
+        } else {
+            digits
+        };
+
+        let sign = if n < 0 {
+            "-"
+        } else if self.plus {
+            "+"
+        } else {
+            ""
+        };
+        let prefix = if self.alternate { prefix } else { "" };
+        self.pad_number(sign, prefix, &digits)
+    }
+
+    /// Pad a number made of sign, radix prefix and digits, zero padding goes
+    /// between the prefix and the digits.
+    fn pad_number(&self, sign: &str, prefix: &str, digits: &str) -> String {
+        let len = sign.len() + prefix.len() + digits.chars().count();
+        if self.zero && self.align.is_none() && len < self.width {
+            let zeros = "0".repeat(self.width - len);
+            return format!("{sign}{prefix}{zeros}{digits}");
+        }
+        self.pad(&format!("{sign}{prefix}{digits}"), Align::Right)
+    }
+
+    /// Pad `s` to the spec's width with its fill and alignment.
+    pub fn pad(&self, s: &str, default: Align) -> String {
+        let len = s.chars().count();
+        if len >= self.width {
+            return s.to_string();
+        }
+        let padding = self.width - len;
+        let (left, right) = match self.align.unwrap_or(default) {
+            Align::Left => (0, padding),
+            Align::Right => (padding, 0),
+            Align::Center => (padding / 2, padding - padding / 2),
+        };
+        let fill = |n| std::iter::repeat_n(self.fill, n).collect::<String>();
+        format!("{}{s}{}", fill(left), fill(right))
+    }
+}
+
+/// Separate digits into groups, thousands for decimal and nibbles or bytes
+/// otherwise.
+fn group(digits: &str, radix: Radix) -> String {
+    let (size, sep) = match radix {
+        Radix::Decimal => (3, ','),
+        Radix::Binary | Radix::LowerHex | Radix::UpperHex => (4, '_'),
+        Radix::Octal => (3, '_'),
+    };
+    let mut grouped = String::new();
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(size) {
+            grouped.push(sep);
+        }
+        grouped.push(c);
+    }
+    grouped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fmt(spec: &str, n: i64) -> String {
+        spec.parse::<Spec>().unwrap().format_int(n)
+    }
+
+    #[test]
+    fn empty_spec_is_plain() {
+        assert_eq!(fmt("", 42), "42");
+        assert_eq!(fmt("", -42), "-42");
+    }
+
+    #[test]
+    fn zero_padding() {
+        assert_eq!(fmt("05", 42), "00042");
+        assert_eq!(fmt("04", -7), "-007");
+        assert_eq!(fmt("03", 12345), "12345");
+    }
+
+    #[test]
+    fn radix() {
+        assert_eq!(fmt("x", 255), "ff");
+        assert_eq!(fmt("X", 255), "FF");
+        assert_eq!(fmt("o", 8), "10");
+        assert_eq!(fmt("b", 5), "101");
+        assert_eq!(fmt("#x", 255), "0xff");
+        assert_eq!(fmt("#010b", 5), "0b00000101");
+        assert_eq!(fmt("x", -255), "-ff");
+    }
+
+    #[test]
+    fn alignment_and_fill() {
+        assert_eq!(fmt(">8", 42), "      42");
+        assert_eq!(fmt("<5", 42), "42   ");
+        assert_eq!(fmt("^6", 42), "  42  ");
+        assert_eq!(fmt("*^7", 42), "**42***");
+        assert_eq!(fmt("8", 42), "      42");
+    }
+
+    #[test]
+    fn thousands_separators() {
+        assert_eq!(fmt(",", 1234567), "1,234,567");
+        assert_eq!(fmt(",", 123), "123");
+        assert_eq!(fmt(",", -1000), "-1,000");
+        assert_eq!(fmt("010,", 1234), "000001,234");
+        assert_eq!(fmt(",b", 0b11110000), "1111_0000");
+    }
+
+    #[test]
+    fn sign() {
+        assert_eq!(fmt("+", 5), "+5");
+        assert_eq!(fmt("+", -5), "-5");
+        assert_eq!(fmt("+04", 5), "+005");
+    }
+
+    #[test]
+    fn floats() {
+        let spec = |s: &str| s.parse::<Spec>().unwrap();
+        assert_eq!(spec(".1").format_value(Value::Float(75.0)).unwrap(), "75.0");
+        assert_eq!(spec(".2").format_value(Value::Int(3)).unwrap(), "3.00");
+        assert_eq!(spec("").format_value(Value::Float(0.5)).unwrap(), "0.5");
+        assert_eq!(
+            spec("08.2").format_value(Value::Float(-12.3456)).unwrap(),
+            "-0012.35"
+        );
+        assert_eq!(
+            spec(",.1").format_value(Value::Float(1234.56)).unwrap(),
+            "1,234.6"
+        );
+        assert!(spec("x").format_value(Value::Float(1.5)).is_err());
+    }
+
+    #[test]
+    fn invalid_specs_are_rejected() {
+        assert!("q".parse::<Spec>().is_err());
+        assert!("05z".parse::<Spec>().is_err());
+        assert!("5.".parse::<Spec>().is_err());
+    }
+
+    #[test]
+    fn huge_sizes_are_rejected() {
+        assert_eq!("1024".parse::<Spec>().unwrap().width, 1024);
+        let e = "1025".parse::<Spec>().unwrap_err();
+        assert_eq!(e.to_string(), "width in format spec '1025' is over 1024");
+        assert!(".99999999999999999999999".parse::<Spec>().is_err());
+        assert!("0>2000000000".parse::<Spec>().is_err());
+    }
+}
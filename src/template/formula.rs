@@ -0,0 +1,53 @@
+use super::expr::{self, Expr};
+use anyhow::{Context, Result};
+use std::fmt;
+use std::str::FromStr;
+
+/// The value of a derived counter, an expression over other counters such
+/// as `{passed} / {total}`. Braces around names are optional.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Formula {
+    pub source: String,
+    pub expr: Expr,
+}
+
+impl FromStr for Formula {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Formula> {
+        // `{name}` reads as a parenthesised name
+        let bare = s.replace('{', "(").replace('}', ")");
+        let expr = expr::parse(&bare).with_context(|| format!("invalid formula '{s}'"))?;
+        Ok(Formula {
+            source: s.to_string(),
+            expr,
+        })
+    }
+}
+
+impl fmt::Display for Formula {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn braces_are_optional() {
+        let braced: Formula = "{passed} / {total}".parse().unwrap();
+        let bare: Formula = "passed / total".parse().unwrap();
+        assert_eq!(braced.expr, bare.expr);
+        assert_eq!(braced.expr.names(), ["passed", "total"]);
+        assert_eq!(braced.to_string(), "{passed} / {total}");
+    }
+
+    #[test]
+    fn rejects_bad_formulas() {
+        for s in ["", "{passed} /", "{passed", "a b"] {
+            assert!(s.parse::<Formula>().is_err(), "{s}");
+        }
+    }
+}
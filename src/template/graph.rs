@@ -0,0 +1,413 @@
+use super::ast::Dependency;
+use super::SELF_NAME;
+use crate::models::{Counter, Kind};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fmt;
+
+/// Which counters each counter's template reads, checked without rendering.
+pub struct Graph {
+    counters: BTreeMap<String, Result<BTreeMap<String, Dependency>, String>>,
+    /// Counters each formula reads, worked out whenever its value is
+    formulas: BTreeMap<String, BTreeSet<String>>,
+}
+
+/// Something that would stop a template from rendering.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Problem {
+    Invalid {
+        counter: String,
+        error: String,
+    },
+    Missing {
+        counter: String,
+        missing: String,
+    },
+    /// Counters referencing each other in a loop, starting from the first by name
+    Cycle(Vec<String>),
+    /// Formulas reading each other's values in a loop, also from the first
+    FormulaCycle(Vec<String>),
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::Invalid { counter, error } => {
+                write!(f, "counter '{counter}' has an invalid template: {error}")
+            }
+            Problem::Missing { counter, missing } => {
+                write!(f, "counter '{counter}' uses missing counter '{missing}'")
+            }
+            Problem::Cycle(names) => {
+                write!(f, "reference cycle {} -> {}", names.join(" -> "), names[0])
+            }
+            Problem::FormulaCycle(names) => {
+                write!(f, "formula cycle {} -> {}", names.join(" -> "), names[0])
+            }
+        }
+    }
+}
+
+impl Graph {
+    pub fn new(counters: &[Counter]) -> Graph {
+        let names: HashSet<_> = counters.iter().map(|c| c.name.as_str()).collect();
+        let formulas = counters
+            .iter()
+            .filter_map(|counter| match &counter.kind {
+                Kind::Formula(formula) => Some((
+                    counter.name.clone(),
+                    formula
+                        .expr
+                        .names()
+                        .into_iter()
+                        // A formula reading `count` reads itself
+                        .map(|name| match name {
+                            SELF_NAME => counter.name.as_str(),
+                            name => name,
+                        })
+                        .filter_map(|name| resolve(&names, name, Dependency::Value))
+                        .map(|(name, _)| name.to_string())
+                        .collect(),
+                )),
+                _ => None,
+            })
+            .collect();
+        let counters = counters
+            .iter()
+            .map(|counter| {
+                let deps = super::parsed(&counter.template)
+                    .map(|template| {
+                        let mut deps = BTreeMap::new();
+                        let mut found = template.dependencies();
+                        // A formula reads counts whatever its template is
+                        if let Kind::Formula(formula) = &counter.kind {
+                            for name in formula.expr.names() {
+                                found.entry(name).or_insert(Dependency::Value);
+                            }
+                        }
+                        for (name, dep) in found {
+                            if let Some((name, dep)) = resolve(&names, name, dep) {
+                                let entry = deps.entry(name.to_string()).or_insert(dep);
+                                *entry = dep.max(*entry);
+                            }
+                        }
+                        deps
+                    })
+                    .map_err(|e| format!("{e:#}"));
+                (counter.name.clone(), deps)
+            })
+            .collect();
+        Graph { counters, formulas }
+    }
+
+    /// Problems rendering `name` would run into, in its own template or the
+    /// ones it references.
+    pub fn check(&self, name: &str) -> Vec<Problem> {
+        let mut problems = BTreeSet::new();
+        let mut search = Search::default();
+        self.visit(name, &mut search, &mut problems);
+        problems.into_iter().collect()
+    }
+
+    /// Problems anywhere in the database.
+    pub fn check_all(&self) -> Vec<Problem> {
+        let mut problems = BTreeSet::new();
+        let mut search = Search::default();
+        for name in self.counters.keys() {
+            self.visit(name, &mut search, &mut problems);
+        }
+        problems.into_iter().collect()
+    }
+
+    fn visit<'a>(
+        &'a self,
+        name: &'a str,
+        search: &mut Search<'a>,
+        problems: &mut BTreeSet<Problem>,
+    ) {
+        if let Some(start) = search.stack.iter().position(|&n| n == name) {
+            problems.insert(Problem::Cycle(rotate(&search.stack[start..])));
+            return;
+        }
+        if !search.done.insert(name) {
+            return;
+        }
+        // Rendering a formula works out its value
+        self.visit_formula(name, search, problems);
+        let deps = match self.counters.get(name) {
+            Some(Ok(deps)) => deps,
+            Some(Err(error)) => {
+                problems.insert(Problem::Invalid {
+                    counter: name.to_string(),
+                    error: error.clone(),
+                });
+                return;
+            }
+            None => return,
+        };
+
+        search.stack.push(name);
+        for (dep, kind) in deps {
+            if !self.counters.contains_key(dep) {
+                problems.insert(Problem::Missing {
+                    counter: name.to_string(),
+                    missing: dep.clone(),
+                });
+            } else if *kind == Dependency::Reference {
+                // Only references render the other template
+                self.visit(dep, search, problems);
+            } else {
+                // and counts can only loop through formulas
+                self.visit_formula(dep, search, problems);
+            }
+        }
+        search.stack.pop();
+    }
+
+    /// Look for formulas reading each other from the formula `name`, if it
+    /// is one.
+    fn visit_formula<'a>(
+        &'a self,
+        name: &'a str,
+        search: &mut Search<'a>,
+        problems: &mut BTreeSet<Problem>,
+    ) {
+        let Some((name, reads)) = self.formulas.get_key_value(name) else {
+            return;
+        };
+        if let Some(start) = search.formulas.iter().position(|&n| n == name) {
+            problems.insert(Problem::FormulaCycle(rotate(&search.formulas[start..])));
+            return;
+        }
+        if !search.formulas_done.insert(name) {
+            return;
+        }
+        search.formulas.push(name);
+        for read in reads {
+            self.visit_formula(read, search, problems);
+        }
+        search.formulas.pop();
+    }
+
+    /// One line per counter listing what it reads.
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+        for (name, deps) in &self.counters {
+            text.push_str(name);
+            if let Ok(deps) = deps {
+                if !deps.is_empty() {
+                    let names: Vec<_> = deps.keys().map(String::as_str).collect();
+                    text.push_str(" -> ");
+                    text.push_str(&names.join(", "));
+                }
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+    /// The graph in Graphviz DOT. References are solid edges and counts read
+    /// by expressions are dashed, broken templates and missing counters red.
+    pub fn dot(&self) -> String {
+        let mut dot = String::from("digraph tally {\n");
+        let mut missing = BTreeSet::new();
+        for (name, deps) in &self.counters {
+            match deps {
+                Ok(_) => dot.push_str(&format!("    {};\n", quote(name))),
+                Err(_) => dot.push_str(&format!("    {} [color=red];\n", quote(name))),
+            }
+        }
+        for (name, deps) in &self.counters {
+            for (dep, kind) in deps.iter().flatten() {
+                if !self.counters.contains_key(dep) {
+                    missing.insert(dep);
+                }
+                let style = match kind {
+                    Dependency::Reference => "",
+                    Dependency::Value => " [style=dashed]",
+                };
+                dot.push_str(&format!("    {} -> {}{style};\n", quote(name), quote(dep)));
+            }
+        }
+        for name in missing {
+            dot.push_str(&format!("    {} [color=red, style=dashed];\n", quote(name)));
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+/// The counter a dependency is on, `app` for a field such as `app.major`,
+/// or nothing for fields of the counter itself.
+fn resolve<'a>(
+    names: &HashSet<&str>,
+    name: &'a str,
+    dep: Dependency,
+) -> Option<(&'a str, Dependency)> {
+    if name == SELF_NAME {
+        return None;
+    }
+    if names.contains(name) {
+        return Some((name, dep));
+    }
+    match name.rsplit_once('.') {
+        Some((SELF_NAME, _)) => None,
+        Some((base, _)) if names.contains(base) => Some((base, Dependency::Value)),
+        _ => Some((name, dep)),
+    }
+}
+
+/// Where a depth first search through the graph has been.
+#[derive(Default)]
+struct Search<'a> {
+    /// Templates being rendered and those done with
+    stack: Vec<&'a str>,
+    done: HashSet<&'a str>,
+    /// Formulas being worked out and those done with
+    formulas: Vec<&'a str>,
+    formulas_done: HashSet<&'a str>,
+}
+
+/// A cycle rotated to start at its first name, so each is reported once.
+fn rotate(names: &[&str]) -> Vec<String> {
+    let start = (0..names.len()).min_by_key(|&i| names[i]).unwrap_or(0);
+    let rotated = names[start..].iter().chain(&names[..start]);
+    rotated.map(|n| n.to_string()).collect()
+}
+
+fn quote(name: &str) -> String {
+    format!("\"{}\"", name.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph(counters: &[(&str, &str)]) -> Graph {
+        let counters: Vec<_> = counters
+            .iter()
+            .map(|(name, template)| Counter {
+                name: name.to_string(),
+                count: 0,
+                step: 1,
+                template: template.to_string(),
+                kind: Default::default(),
+                scope: None,
+            })
+            .collect();
+        Graph::new(&counters)
+    }
+
+    fn messages(problems: Vec<Problem>) -> Vec<String> {
+        problems.iter().map(Problem::to_string).collect()
+    }
+
+    #[test]
+    fn clean_graph_has_no_problems() {
+        let g = graph(&[("a", "{b}-{c:02}"), ("b", "{}"), ("c", "{a + 1}")]);
+        assert!(g.check_all().is_empty());
+        assert!(g.check("a").is_empty());
+    }
+
+    #[test]
+    fn reports_missing_counters() {
+        let g = graph(&[("a", "{b}"), ("b", "{ghost * 2}"), ("c", "{}")]);
+        let expected = vec!["counter 'b' uses missing counter 'ghost'"];
+        assert_eq!(messages(g.check("a")), expected);
+        assert_eq!(messages(g.check_all()), expected);
+        assert!(g.check("c").is_empty());
+    }
+
+    #[test]
+    fn reports_each_cycle_once() {
+        let g = graph(&[("a", "{b}"), ("b", "{c}"), ("c", "{a}"), ("d", "{d}")]);
+        assert_eq!(
+            messages(g.check_all()),
+            vec!["reference cycle a -> b -> c -> a", "reference cycle d -> d"]
+        );
+        assert_eq!(
+            messages(g.check("b")),
+            vec!["reference cycle a -> b -> c -> a"]
+        );
+    }
+
+    #[test]
+    fn counts_read_in_a_loop_are_fine() {
+        let g = graph(&[("a", "{b + 1}"), ("b", "{a + 1}")]);
+        assert!(g.check_all().is_empty());
+    }
+
+    #[test]
+    fn count_is_the_counter_rendering() {
+        let g = graph(&[("a", "{count} {count.major}"), ("count", "{a}")]);
+        assert_eq!(messages(g.check_all()), Vec::<String>::new());
+
+        // In a formula it's the formula itself
+        let mut f = Counter::new("f");
+        f.kind = Kind::Formula("{count} + 1".parse().unwrap());
+        let mut counters = vec![f];
+        assert_eq!(
+            messages(Graph::new(&counters).check("f")),
+            vec!["formula cycle f -> f"]
+        );
+        counters[0].kind = Kind::Formula("{count.major} + 1".parse().unwrap());
+        assert!(Graph::new(&counters).check("f").is_empty());
+    }
+
+    #[test]
+    fn formulas_reading_each_other_loop() {
+        let mut counters: Vec<_> = [("a", "{b} + 1"), ("b", "{a} + 1"), ("c", "{b} * 2")]
+            .iter()
+            .map(|(name, formula)| {
+                let mut counter = Counter::new(name);
+                counter.kind = Kind::Formula(formula.parse().unwrap());
+                counter
+            })
+            .collect();
+        counters.push(Counter {
+            template: "{c}".to_string(),
+            ..Counter::new("d")
+        });
+        let g = Graph::new(&counters);
+        let expected = vec!["formula cycle a -> b -> a"];
+        assert_eq!(messages(g.check_all()), expected);
+        assert_eq!(messages(g.check("d")), expected);
+
+        // A formula reading a counter whose template reads it back is fine
+        let mut ratio = Counter::new("ratio");
+        ratio.kind = Kind::Formula("{total} / 2".parse().unwrap());
+        let total = Counter {
+            template: "{ratio}".to_string(),
+            ..Counter::new("total")
+        };
+        assert!(Graph::new(&[ratio, total]).check_all().is_empty());
+    }
+
+    #[test]
+    fn fields_depend_on_their_counter() {
+        let g = graph(&[
+            ("app", "{}"),
+            ("tag", "v{app.major}.{app.minor}{count.major}"),
+        ]);
+        assert_eq!(g.text(), "app\ntag -> app\n");
+        assert!(g.check_all().is_empty());
+        assert!(g.dot().contains("\"tag\" -> \"app\" [style=dashed];"));
+    }
+
+    #[test]
+    fn reports_invalid_templates() {
+        let g = graph(&[("a", "{b}"), ("b", "{oops")]);
+        let problems = messages(g.check("a"));
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].starts_with("counter 'b' has an invalid template"));
+    }
+
+    #[test]
+    fn renders_text_and_dot() {
+        let g = graph(&[("a", "{b}{c:02}"), ("b", "{}"), ("c", "{ghost}")]);
+        assert_eq!(g.text(), "a -> b, c\nb\nc -> ghost\n");
+        assert_eq!(
+            g.dot(),
+            "digraph tally {\n    \"a\";\n    \"b\";\n    \"c\";\n    \"a\" -> \"b\";\n    \"a\" -> \"c\" [style=dashed];\n    \"c\" -> \"ghost\";\n    \"ghost\" [color=red, style=dashed];\n}\n"
+        );
+    }
+}
@@ -0,0 +1,264 @@
+use super::expr::Value;
+use anyhow::{bail, Result};
+
+pub fn roman(value: Value) -> Result<String> {
+    const NUMERALS: [(i64, &str); 13] = [
+        (1000, "M"),
+        (900, "CM"),
+        (500, "D"),
+        (400, "CD"),
+        (100, "C"),
+        (90, "XC"),
+        (50, "L"),
+        (40, "XL"),
+        (10, "X"),
+        (9, "IX"),
+        (5, "V"),
+        (4, "IV"),
+        (1, "I"),
+    ];
+    let mut n = int(value, "roman")?;
+    if !(1..=3999).contains(&n) {
+        bail!("roman numerals only go from 1 to 3999, not {n}");
+    }
+    let mut roman = String::new();
+    for (size, numeral) in NUMERALS {
+        while n >= size {
+            roman.push_str(numeral);
+            n -= size;
+        }
+    }
+    Ok(roman)
+}
+
+pub fn words(value: Value) -> Result<String> {
+    const SCALES: [(u64, &str); 6] = [
+        (1_000_000_000_000_000_000, "quintillion"),
+        (1_000_000_000_000_000, "quadrillion"),
+        (1_000_000_000_000, "trillion"),
+        (1_000_000_000, "billion"),
+        (1_000_000, "million"),
+        (1_000, "thousand"),
+    ];
+    let n = int(value, "words")?;
+    if n == 0 {
+        return Ok("zero".to_string());
+    }
+    let mut parts = Vec::new();
+    if n < 0 {
+        parts.push("minus".to_string());
+    }
+    let mut rest = n.unsigned_abs();
+    for (size, scale) in SCALES {
+        if rest >= size {
+            parts.push(format!("{} {scale}", below_thousand(rest / size)));
+            rest %= size;
+        }
+    }
+    if rest > 0 {
+        parts.push(below_thousand(rest));
+    }
+    Ok(parts.join(" "))
+}
+
+fn below_thousand(n: u64) -> String {
+    const ONES: [&str; 20] = [
+        "",
+        "one",
+        "two",
+        "three",
+        "four",
+        "five",
+        "six",
+        "seven",
+        "eight",
+        "nine",
+        "ten",
+        "eleven",
+        "twelve",
+        "thirteen",
+        "fourteen",
+        "fifteen",
+        "sixteen",
+        "seventeen",
+        "eighteen",
+        "nineteen",
+    ];
+    const TENS: [&str; 10] = [
+        "", "", "twenty", "thirty", "forty", "fifty", "sixty", "seventy", "eighty", "ninety",
+    ];
+    let mut parts = Vec::new();
+    if n >= 100 {
+        parts.push(format!("{} hundred", ONES[(n / 100) as usize]));
+    }
+    let n = (n % 100) as usize;
+    match n {
+        0 => {}
+        1..=19 => parts.push(ONES[n].to_string()),
+        _ if n.is_multiple_of(10) => parts.push(TENS[n / 10].to_string()),
+        _ => parts.push(format!("{}-{}", TENS[n / 10], ONES[n % 10])),
+    }
+    parts.join(" ")
+}
+
+pub fn ordinal(value: Value) -> Result<String> {
+    let n = int(value, "ordinal")?;
+    let last_two = n.unsigned_abs() % 100;
+    let suffix = match (last_two % 10, last_two) {
+        (_, 11..=13) => "th",
+        (1, _) => "st",
+        (2, _) => "nd",
+        (3, _) => "rd",
+        _ => "th",
+    };
+    Ok(format!("{n}{suffix}"))
+}
+
+/// Metric prefixes, `1.2k` or `3.4M`.
+pub fn si(value: Value) -> Result<String> {
+    Ok(scaled(
+        float(value),
+        1000.0,
+        &["", "k", "M", "G", "T", "P", "E"],
+        "",
+    ))
+}
+
+/// Binary prefixes, `512 B` or `1.5 MiB`.
+pub fn bytes(value: Value) -> Result<String> {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+    Ok(scaled(float(value), 1024.0, &units, " "))
+}
+
+/// Divide `n` down to below `base`, keeping one decimal once it's scaled.
+fn scaled(n: f64, base: f64, units: &[&str], sep: &str) -> String {
+    let sign = if n < 0.0 { "-" } else { "" };
+    let mut n = n.abs();
+    let mut unit = 0;
+    // Round first so 999.96k carries over to 1M rather than printing 1000k
+    while unit + 1 < units.len() && (n * 10.0).round() / 10.0 >= base {
+        n /= base;
+        unit += 1;
+    }
+    let n = format!("{:.1}", n);
+    let n = n.strip_suffix(".0").unwrap_or(&n);
+    format!("{sign}{n}{sep}{}", units[unit])
+}
+
+/// Seconds as `1d 2h 3m 4s`, leaving out the parts that are zero.
+pub fn duration(value: Value) -> Result<String> {
+    const UNITS: [(u64, &str); 4] = [(86_400, "d"), (3_600, "h"), (60, "m"), (1, "s")];
+    let seconds = float(value).round();
+    let sign = if seconds < 0.0 { "-" } else { "" };
+    let mut rest = seconds.abs() as u64;
+    if rest == 0 {
+        return Ok("0s".to_string());
+    }
+    let mut parts = Vec::new();
+    for (size, unit) in UNITS {
+        if rest >= size {
+            parts.push(format!("{}{unit}", rest / size));
+            rest %= size;
+        }
+    }
+    Ok(format!("{sign}{}", parts.join(" ")))
+}
+
+fn int(value: Value, name: &str) -> Result<i64> {
+    match value {
+        Value::Int(n) => Ok(n),
+        Value::Float(f) => bail!("{name} needs a whole number, not {f}"),
+    }
+}
+
+fn float(value: Value) -> f64 {
+    match value {
+        Value::Int(n) => n as f64,
+        Value::Float(f) => f,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(f: fn(Value) -> Result<String>, n: i64) -> String {
+        f(Value::Int(n)).unwrap()
+    }
+
+    #[test]
+    fn roman_numerals() {
+        assert_eq!(apply(roman, 1), "I");
+        assert_eq!(apply(roman, 4), "IV");
+        assert_eq!(apply(roman, 14), "XIV");
+        assert_eq!(apply(roman, 1994), "MCMXCIV");
+        assert_eq!(apply(roman, 3999), "MMMCMXCIX");
+        assert!(roman(Value::Int(0)).is_err());
+        assert!(roman(Value::Int(4000)).is_err());
+        assert!(roman(Value::Float(2.5)).is_err());
+    }
+
+    #[test]
+    fn number_words() {
+        assert_eq!(apply(words, 0), "zero");
+        assert_eq!(apply(words, 7), "seven");
+        assert_eq!(apply(words, 42), "forty-two");
+        assert_eq!(apply(words, 90), "ninety");
+        assert_eq!(apply(words, 115), "one hundred fifteen");
+        assert_eq!(apply(words, 1_000_021), "one million twenty-one");
+        assert_eq!(apply(words, -3), "minus three");
+        assert!(apply(words, i64::MIN).starts_with("minus nine quintillion"));
+    }
+
+    #[test]
+    fn ordinals() {
+        let cases = [
+            (1, "1st"),
+            (2, "2nd"),
+            (3, "3rd"),
+            (4, "4th"),
+            (11, "11th"),
+            (12, "12th"),
+            (13, "13th"),
+            (21, "21st"),
+            (102, "102nd"),
+            (111, "111th"),
+            (0, "0th"),
+            (-1, "-1st"),
+        ];
+        for (n, expected) in cases {
+            assert_eq!(apply(ordinal, n), expected);
+        }
+    }
+
+    #[test]
+    fn si_prefixes() {
+        assert_eq!(apply(si, 999), "999");
+        assert_eq!(apply(si, 1000), "1k");
+        assert_eq!(apply(si, 1234), "1.2k");
+        assert_eq!(apply(si, 3_400_000), "3.4M");
+        assert_eq!(apply(si, 999_960), "1M");
+        assert_eq!(apply(si, -1500), "-1.5k");
+        assert_eq!(si(Value::Float(0.3)).unwrap(), "0.3");
+    }
+
+    #[test]
+    fn byte_sizes() {
+        assert_eq!(apply(bytes, 512), "512 B");
+        assert_eq!(apply(bytes, 1024), "1 KiB");
+        assert_eq!(apply(bytes, 1536), "1.5 KiB");
+        assert_eq!(apply(bytes, 5 * 1024 * 1024), "5 MiB");
+        assert_eq!(apply(bytes, i64::MAX), "8 EiB");
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(apply(duration, 0), "0s");
+        assert_eq!(apply(duration, 45), "45s");
+        assert_eq!(apply(duration, 3600), "1h");
+        assert_eq!(apply(duration, 3725), "1h 2m 5s");
+        assert_eq!(apply(duration, 2 * 86_400 + 60), "2d 1m");
+        assert_eq!(apply(duration, -65), "-1m 5s");
+        assert_eq!(duration(Value::Float(59.6)).unwrap(), "1m");
+    }
+}
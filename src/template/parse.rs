@@ -0,0 +1,112 @@
+use anyhow::{bail, Result};
+
+/// A run of literal text or the contents of a `{...}` tag.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Piece<'a> {
+    Text(String),
+    Tag(&'a str),
+}
+
+/// Split a template into text and tags. `{{` and `}}` stand for literal
+/// braces, and braces inside a tag's quoted strings don't count.
+pub fn lex(template: &str) -> Result<Vec<Piece<'_>>> {
+    let mut pieces = Vec::new();
+    let mut text = String::new();
+    let mut chars = template.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '{' if chars.next_if(|&(_, c)| c == '{').is_some() => text.push('{'),
+            '}' if chars.next_if(|&(_, c)| c == '}').is_some() => text.push('}'),
+            '}' => bail!("unmatched '}}' at offset {i}, write '}}}}' for a literal brace"),
+            '{' => {
+                let start = i + 1;
+                let mut end = None;
+                let mut in_string = false;
+                for (j, c) in chars.by_ref() {
+                    match c {
+                        '"' => in_string = !in_string,
+                        '}' if !in_string => {
+                            end = Some(j);
+                            break;
+                        }
+                        '{' if !in_string => {
+                            bail!("unexpected '{{' at offset {j} inside the tag at offset {i}")
+                        }
+                        _ => {}
+                    }
+                }
+                let Some(end) = end else {
+                    bail!("unclosed '{{' at offset {i}, write '{{{{' for a literal brace");
+                };
+                if !text.is_empty() {
+                    pieces.push(Piece::Text(std::mem::take(&mut text)));
+                }
+                pieces.push(Piece::Tag(&template[start..end]));
+            }
+            c => text.push(c),
+        }
+    }
+    if !text.is_empty() {
+        pieces.push(Piece::Text(text));
+    }
+    Ok(pieces)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(s: &str) -> Piece<'_> {
+        Piece::Text(s.to_string())
+    }
+
+    #[test]
+    fn splits_text_and_tags() {
+        assert_eq!(
+            lex("v{}-{build:04}!").unwrap(),
+            vec![
+                text("v"),
+                Piece::Tag(""),
+                text("-"),
+                Piece::Tag("build:04"),
+                text("!")
+            ]
+        );
+        assert_eq!(lex("").unwrap(), vec![]);
+    }
+
+    #[test]
+    fn doubled_braces_are_literal() {
+        assert_eq!(
+            lex(r#"{{"count": {}}}"#).unwrap(),
+            vec![text(r#"{"count": "#), Piece::Tag(""), text("}")]
+        );
+        assert_eq!(lex("${{HOME}}").unwrap(), vec![text("${HOME}")]);
+    }
+
+    #[test]
+    fn braces_in_strings_stay_in_the_tag() {
+        assert_eq!(
+            lex(r#"{plural count "{" "}}"}"#).unwrap(),
+            vec![Piece::Tag(r#"plural count "{" "}}""#)]
+        );
+    }
+
+    #[test]
+    fn unbalanced_braces_error() {
+        let err = |t| lex(t).unwrap_err().to_string();
+        assert!(
+            err("a}b").contains("unmatched '}' at offset 1"),
+            "{}",
+            err("a}b")
+        );
+        assert!(
+            err("ab{c").contains("unclosed '{' at offset 2"),
+            "{}",
+            err("ab{c")
+        );
+        assert!(err("{a{b}").contains("unexpected '{'"), "{}", err("{a{b}"));
+        assert!(lex(r#"{plural count "x}"#).is_err());
+    }
+}
@@ -0,0 +1,460 @@
+use crate::app;
+use crate::cli::SetArgs;
+use crate::clock;
+use crate::database::Connection;
+use crate::models::Counter;
+use crate::template;
+use anyhow::{anyhow, bail, Context, Result};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Position};
+use ratatui::style::{Modifier, Style};
+use ratatui::widgets::{Paragraph, Row, Table, TableState};
+use ratatui::{DefaultTerminal, Frame};
+use std::io::IsTerminal;
+use std::path::Path;
+use std::time::Duration;
+
+/// How long to wait for a key before looking for changes made elsewhere.
+const TICK: Duration = Duration::from_millis(250);
+
+const HELP: &str = "↑↓ select  + add  - sub  r reset  s step  t template  d default  q quit";
+
+/// A counter as it's shown on a row of the dashboard.
+#[derive(Debug, Clone, PartialEq)]
+struct Item {
+    name: String,
+    value: String,
+    step: i64,
+    template: String,
+    spark: String,
+    default: bool,
+}
+
+/// Setting of the selected counter being typed in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Field {
+    Step,
+    Template,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Mode {
+    Browse,
+    Edit { field: Field, input: String },
+}
+
+/// A change asked for from the keyboard, made to the selected counter.
+#[derive(Debug, Clone, PartialEq)]
+enum Action {
+    Add,
+    Sub,
+    Reset,
+    Step(String),
+    Template(String),
+    Default,
+    Quit,
+}
+
+struct Dashboard {
+    items: Vec<Item>,
+    table: TableState,
+    mode: Mode,
+    /// Outcome of the last change when it failed, until the next key
+    status: Option<String>,
+}
+
+/// Show every counter with its value and latest changes, changing them
+/// from the keyboard until the user quits.
+pub fn run(
+    database_path: &Path,
+    lock_timeout: Option<Duration>,
+    scope: Option<&str>,
+) -> Result<()> {
+    if !std::io::stdout().is_terminal() {
+        bail!("top needs a terminal, use 'tally watch' to follow counters from a script");
+    }
+    let conn = Connection::open_shared(&database_path.to_string_lossy())?;
+    let mut dashboard = Dashboard::new();
+    let mut terminal = ratatui::try_init()?;
+    let result = dashboard.show(&mut terminal, &conn, scope, |name, action| {
+        apply(database_path, lock_timeout, scope, name, action)
+    });
+    ratatui::restore();
+    result
+}
+
+/// Make a change to the named counter through the same paths as the
+/// commands, holding the lock only while doing so.
+fn apply(
+    database_path: &Path,
+    lock_timeout: Option<Duration>,
+    scope: Option<&str>,
+    name: &str,
+    action: Action,
+) -> Result<()> {
+    let conn = Connection::open(&database_path.to_string_lossy(), lock_timeout)?;
+    let mut counter = Counter::get(conn.get(), name)?
+        .ok_or_else(|| anyhow!("counter '{name}' no longer exists"))?;
+    if let Some(scope) = scope {
+        counter = counter.with_scope(conn.get(), scope)?;
+    }
+
+    let args = match action {
+        Action::Add => {
+            app::addable(&counter)?;
+            let before = counter.count;
+            counter.add(counter.step, clock::today()?);
+            return app::save(&conn, &counter, before);
+        }
+        Action::Sub => {
+            app::addable(&counter)?;
+            let before = counter.count;
+            counter.count -= counter.step;
+            return app::save(&conn, &counter, before);
+        }
+        Action::Quit => return Ok(()),
+        Action::Reset => SetArgs {
+            count: Some(0),
+            ..SetArgs::default()
+        },
+        Action::Step(input) => SetArgs {
+            step: Some(
+                input
+                    .trim()
+                    .parse()
+                    .map_err(|_| anyhow!("step must be a whole number, got '{input}'"))?,
+            ),
+            ..SetArgs::default()
+        },
+        Action::Template(input) => {
+            template::validate(&input).with_context(|| format!("invalid template '{input}'"))?;
+            SetArgs {
+                template: Some(input),
+                ..SetArgs::default()
+            }
+        }
+        Action::Default => SetArgs {
+            default: true,
+            ..SetArgs::default()
+        },
+    };
+    app::set(&conn, &mut counter, args)
+}
+
+impl Dashboard {
+    fn new() -> Dashboard {
+        Dashboard {
+            items: Vec::new(),
+            table: TableState::default(),
+            mode: Mode::Browse,
+            status: None,
+        }
+    }
+
+    fn show(
+        &mut self,
+        terminal: &mut DefaultTerminal,
+        conn: &Connection,
+        scope: Option<&str>,
+        mut apply: impl FnMut(&str, Action) -> Result<()>,
+    ) -> Result<()> {
+        let mut version = None;
+        loop {
+            // Only look at the counters once something has been committed
+            let current = conn.data_version()?;
+            if version != Some(current) {
+                version = Some(current);
+                self.load(conn, scope)?;
+            }
+            terminal.draw(|frame| self.draw(frame))?;
+
+            if !event::poll(TICK)? {
+                continue;
+            }
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            match self.key(key) {
+                None => {}
+                Some(Action::Quit) => return Ok(()),
+                Some(action) => {
+                    if let Some(item) = self.selected() {
+                        let name = item.name.clone();
+                        if let Err(e) = apply(&name, action) {
+                            self.status = Some(format!("{e:#}"));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Read every counter again, keeping the same one selected.
+    fn load(&mut self, conn: &Connection, scope: Option<&str>) -> Result<()> {
+        let default = Counter::get_default(conn.get())?;
+        let selected = self.selected().map(|item| item.name.clone());
+        let mut items = Vec::new();
+        for mut counter in Counter::get_all(conn.get())? {
+            if let Some(scope) = scope {
+                counter = counter.with_scope(conn.get(), scope)?;
+            }
+            items.push(Item {
+                value: template::display(conn, &counter).unwrap_or_else(|_| "?".to_string()),
+                spark: template::sparkline(conn, &counter)?,
+                default: default.as_deref() == Some(counter.name.as_str()),
+                name: counter.name,
+                step: counter.step,
+                template: counter.template,
+            });
+        }
+        self.items = items;
+
+        let index = selected
+            .and_then(|name| self.items.iter().position(|item| item.name == name))
+            .or(self.table.selected())
+            .unwrap_or(0);
+        self.table
+            .select((!self.items.is_empty()).then(|| index.min(self.items.len() - 1)));
+        Ok(())
+    }
+
+    fn selected(&self) -> Option<&Item> {
+        self.items.get(self.table.selected()?)
+    }
+
+    /// Act on a key press, returning the change it asks for if any.
+    fn key(&mut self, key: KeyEvent) -> Option<Action> {
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            return Some(Action::Quit);
+        }
+        self.status = None;
+
+        if let Mode::Edit { field, input } = &mut self.mode {
+            match key.code {
+                KeyCode::Enter => {
+                    let input = std::mem::take(input);
+                    let action = match field {
+                        Field::Step => Action::Step(input),
+                        Field::Template => Action::Template(input),
+                    };
+                    self.mode = Mode::Browse;
+                    return Some(action);
+                }
+                KeyCode::Esc => self.mode = Mode::Browse,
+                KeyCode::Backspace => {
+                    input.pop();
+                }
+                KeyCode::Char(c) => input.push(c),
+                _ => {}
+            }
+            return None;
+        }
+
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Some(Action::Quit),
+            KeyCode::Up | KeyCode::Char('k') => self.table.select_previous(),
+            KeyCode::Down | KeyCode::Char('j')
+                if self.table.selected() < Some(self.items.len().saturating_sub(1)) =>
+            {
+                self.table.select_next()
+            }
+            _ => {}
+        }
+        let item = self.selected()?;
+        match key.code {
+            KeyCode::Char('+') | KeyCode::Char('=') => Some(Action::Add),
+            KeyCode::Char('-') => Some(Action::Sub),
+            KeyCode::Char('r') => Some(Action::Reset),
+            KeyCode::Char('d') => Some(Action::Default),
+            KeyCode::Char('s') => {
+                self.mode = Mode::Edit {
+                    field: Field::Step,
+                    input: item.step.to_string(),
+                };
+                None
+            }
+            KeyCode::Char('t') => {
+                self.mode = Mode::Edit {
+                    field: Field::Template,
+                    input: item.template.clone(),
+                };
+                None
+            }
+            _ => None,
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [body, footer] =
+            Layout::vertical([Constraint::Fill(1), Constraint::Length(1)]).areas(frame.area());
+
+        let header = ["Name", "Value", "Step", "Template", "History", "Default"];
+        let rows: Vec<[String; 6]> = self
+            .items
+            .iter()
+            .map(|item| {
+                [
+                    item.name.clone(),
+                    item.value.clone(),
+                    item.step.to_string(),
+                    item.template.clone(),
+                    item.spark.clone(),
+                    if item.default { "*" } else { "" }.to_string(),
+                ]
+            })
+            .collect();
+        let width = |column: usize| {
+            rows.iter()
+                .map(|row| row[column].chars().count())
+                .chain([header[column].len()])
+                .max()
+                .unwrap_or(0) as u16
+        };
+        let widths = [
+            Constraint::Length(width(0)),
+            Constraint::Length(width(1)),
+            Constraint::Length(width(2)),
+            Constraint::Fill(1),
+            Constraint::Length(width(4)),
+            Constraint::Length(width(5)),
+        ];
+        let table = Table::new(rows.into_iter().map(Row::new), widths)
+            .header(Row::new(header).style(Style::new().add_modifier(Modifier::BOLD)))
+            .column_spacing(2)
+            .row_highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(table, body, &mut self.table);
+
+        let line = match (&self.mode, &self.status) {
+            (Mode::Edit { field, input }, _) => {
+                let prompt = match field {
+                    Field::Step => "Step: ",
+                    Field::Template => "Template: ",
+                };
+                let x = footer.x + (prompt.len() + input.chars().count()) as u16;
+                frame.set_cursor_position(Position::new(x.min(footer.right()), footer.y));
+                format!("{prompt}{input}")
+            }
+            (Mode::Browse, Some(status)) => status.clone(),
+            (Mode::Browse, None) => HELP.to_string(),
+        };
+        frame.render_widget(Paragraph::new(line), footer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Kind;
+    use ratatui::crossterm::event::KeyCode::Char;
+    use tempfile::TempDir;
+
+    fn press(dashboard: &mut Dashboard, code: KeyCode) -> Option<Action> {
+        dashboard.key(KeyEvent::from(code))
+    }
+
+    fn database(dir: &TempDir) -> (std::path::PathBuf, Connection) {
+        let path = dir.path().join("t.db");
+        let conn = Connection::new(&path.to_string_lossy()).unwrap();
+        for name in ["builds", "queue"] {
+            Counter::new(name).insert(conn.get()).unwrap();
+        }
+        // Changes take the lock, so read without it like the dashboard
+        drop(conn);
+        let conn = Connection::open_shared(&path.to_string_lossy()).unwrap();
+        (path, conn)
+    }
+
+    #[test]
+    fn keys_select_and_edit_counters() {
+        let dir = TempDir::new().unwrap();
+        let (_, conn) = database(&dir);
+        let mut dashboard = Dashboard::new();
+        dashboard.load(&conn, None).unwrap();
+        let names: Vec<_> = dashboard.items.iter().map(|i| i.name.as_str()).collect();
+        assert_eq!(names, ["tally", "builds", "queue"]);
+        assert_eq!(dashboard.selected().unwrap().name, "tally");
+        assert!(dashboard.items[0].default);
+
+        for _ in 0..5 {
+            press(&mut dashboard, KeyCode::Down);
+        }
+        assert_eq!(dashboard.selected().unwrap().name, "queue");
+        press(&mut dashboard, Char('k'));
+        assert_eq!(dashboard.selected().unwrap().name, "builds");
+        assert_eq!(press(&mut dashboard, Char('+')), Some(Action::Add));
+        assert_eq!(press(&mut dashboard, Char('-')), Some(Action::Sub));
+
+        // The step starts out as it is and is handed over on enter
+        assert_eq!(press(&mut dashboard, Char('s')), None);
+        press(&mut dashboard, KeyCode::Backspace);
+        assert_eq!(press(&mut dashboard, Char('q')), None);
+        press(&mut dashboard, KeyCode::Backspace);
+        press(&mut dashboard, Char('5'));
+        assert_eq!(
+            press(&mut dashboard, KeyCode::Enter),
+            Some(Action::Step("5".to_string()))
+        );
+
+        press(&mut dashboard, Char('t'));
+        press(&mut dashboard, Char('x'));
+        assert_eq!(press(&mut dashboard, KeyCode::Esc), None);
+        assert_eq!(dashboard.mode, Mode::Browse);
+        assert_eq!(press(&mut dashboard, Char('q')), Some(Action::Quit));
+    }
+
+    #[test]
+    fn selection_follows_the_counter_across_reloads() {
+        let dir = TempDir::new().unwrap();
+        let (_, conn) = database(&dir);
+        let mut dashboard = Dashboard::new();
+        dashboard.load(&conn, None).unwrap();
+        press(&mut dashboard, KeyCode::Down);
+        assert_eq!(dashboard.selected().unwrap().name, "builds");
+
+        Counter::delete(conn.get(), "tally").unwrap();
+        dashboard.load(&conn, None).unwrap();
+        assert_eq!(dashboard.selected().unwrap().name, "builds");
+
+        // A deleted counter leaves the one after it selected
+        Counter::delete(conn.get(), "builds").unwrap();
+        dashboard.load(&conn, None).unwrap();
+        assert_eq!(dashboard.selected().unwrap().name, "queue");
+    }
+
+    #[test]
+    fn actions_change_the_counter() {
+        let dir = TempDir::new().unwrap();
+        let (path, conn) = database(&dir);
+        let apply = |name: &str, action| apply(&path, None, None, name, action);
+        let get = |name: &str| Counter::get(conn.get(), name).unwrap().unwrap();
+
+        apply("builds", Action::Step("3".to_string())).unwrap();
+        apply("builds", Action::Add).unwrap();
+        apply("builds", Action::Add).unwrap();
+        apply("builds", Action::Sub).unwrap();
+        assert_eq!((get("builds").count, get("builds").step), (3, 3));
+
+        apply("builds", Action::Template("#{}".to_string())).unwrap();
+        assert_eq!(get("builds").template, "#{}");
+        assert!(apply("builds", Action::Template("{".to_string())).is_err());
+        assert!(apply("builds", Action::Step("x".to_string())).is_err());
+
+        apply("builds", Action::Reset).unwrap();
+        assert_eq!(get("builds").count, 0);
+        apply("builds", Action::Default).unwrap();
+        assert_eq!(
+            Counter::get_default(conn.get()).unwrap().as_deref(),
+            Some("builds")
+        );
+
+        let mut queue = get("queue");
+        queue.kind = Kind::Gauge(crate::gauge::Gauge::new(4));
+        queue.update(conn.get()).unwrap();
+        let e = apply("queue", Action::Add).unwrap_err();
+        assert!(e.to_string().contains("is a gauge"), "{e}");
+        assert!(apply("gone", Action::Add).is_err());
+    }
+}
@@ -0,0 +1,422 @@
+use anyhow::{anyhow, bail, Result};
+use chrono::{Datelike, NaiveDate};
+use clap::ValueEnum;
+use std::fmt;
+use std::str::FromStr;
+
+/// A `major.minor.patch[-pre]` version, as in semver.org.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Semver {
+    pub major: u64,
+    pub minor: u64,
+    pub patch: u64,
+    pub pre: Option<String>,
+}
+
+/// Component of a version to bump.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Part {
+    Major,
+    Minor,
+    Patch,
+    Pre,
+}
+
+impl Semver {
+    /// Bump `part` and reset the ones below it. Bumping a pre-release
+    /// releases it first where that's enough, so 1.3.0-rc.2 bumped by
+    /// minor is 1.3.0, the way npm does it.
+    pub fn bump(&mut self, part: Part, pre_id: Option<&str>) -> Result<()> {
+        let current = self.to_string();
+        let overflow = || anyhow!("version {current} can't be bumped any further");
+        match part {
+            Part::Major => {
+                if self.pre.is_none() || self.minor != 0 || self.patch != 0 {
+                    self.major = self.major.checked_add(1).ok_or_else(overflow)?;
+                }
+                self.minor = 0;
+                self.patch = 0;
+                self.pre = None;
+            }
+            Part::Minor => {
+                if self.pre.is_none() || self.patch != 0 {
+                    self.minor = self.minor.checked_add(1).ok_or_else(overflow)?;
+                }
+                self.patch = 0;
+                self.pre = None;
+            }
+            Part::Patch => {
+                if self.pre.is_none() {
+                    self.patch = self.patch.checked_add(1).ok_or_else(overflow)?;
+                }
+                self.pre = None;
+            }
+            Part::Pre => {
+                if let Some(id) = pre_id {
+                    validate_pre(id)?;
+                }
+                self.pre = Some(match (self.pre.take(), pre_id) {
+                    // Switching to another pre-release line starts it over
+                    (Some(pre), Some(id)) if pre.split('.').next() != Some(id) => format!("{id}.0"),
+                    (Some(pre), _) => next_pre(&pre),
+                    (None, id) => {
+                        self.patch = self.patch.checked_add(1).ok_or_else(overflow)?;
+                        id.map_or("0".to_string(), |id| format!("{id}.0"))
+                    }
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Numeric component by name, for `{app.major}` in templates.
+    pub fn field(&self, name: &str) -> Option<i64> {
+        let n = match name {
+            "major" => self.major,
+            "minor" => self.minor,
+            "patch" => self.patch,
+            _ => return None,
+        };
+        i64::try_from(n).ok()
+    }
+}
+
+/// Increment the last numeric identifier, or start counting after the
+/// existing ones, `rc.1` to `rc.2` and `beta` to `beta.0`.
+fn next_pre(pre: &str) -> String {
+    match pre.rsplit_once('.').unwrap_or(("", pre)) {
+        (head, last) if last.parse::<u64>().is_ok() => {
+            let next = last.parse::<u64>().unwrap().saturating_add(1);
+            if head.is_empty() {
+                next.to_string()
+            } else {
+                format!("{head}.{next}")
+            }
+        }
+        _ => format!("{pre}.0"),
+    }
+}
+
+fn validate_pre(pre: &str) -> Result<()> {
+    let valid =
+        |id: &str| !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+    if !pre.split('.').all(valid) {
+        bail!("invalid pre-release '{pre}', use dot separated letters, digits and '-'");
+    }
+    Ok(())
+}
+
+impl FromStr for Semver {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Semver> {
+        let invalid = || anyhow!("invalid version '{s}', expected major.minor.patch[-pre]");
+        let version = s.strip_prefix('v').unwrap_or(s);
+        let (core, pre) = match version.split_once('-') {
+            Some((core, pre)) => (core, Some(pre)),
+            None => (version, None),
+        };
+        let parts: Vec<_> = core
+            .split('.')
+            .map(|n| n.parse::<u64>().map_err(|_| invalid()))
+            .collect::<Result<_>>()?;
+        let [major, minor, patch] = parts[..] else {
+            return Err(invalid());
+        };
+        if let Some(pre) = pre {
+            validate_pre(pre)?;
+        }
+        Ok(Semver {
+            major,
+            minor,
+            patch,
+            pre: pre.map(str::to_string),
+        })
+    }
+}
+
+impl fmt::Display for Semver {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)?;
+        if let Some(pre) = &self.pre {
+            write!(f, "-{pre}")?;
+        }
+        Ok(())
+    }
+}
+
+/// A calendar version such as `2026.10.3`, the date it was last released
+/// on and a scheme saying how to write it. The count is kept by the counter.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Calver {
+    pub scheme: Scheme,
+    pub date: NaiveDate,
+}
+
+/// How a calendar version is written, in the tokens of calver.org plus `N`
+/// for the release within the period, as in `YYYY.0M.N`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Scheme(String);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Token {
+    Literal(char),
+    /// Full year, `2026`
+    Year,
+    /// Year since 2000, `26`, or `06` when padded
+    ShortYear {
+        padded: bool,
+    },
+    Month {
+        padded: bool,
+    },
+    /// ISO week of the year
+    Week {
+        padded: bool,
+    },
+    Day {
+        padded: bool,
+    },
+    N,
+}
+
+const TOKENS: &[(&str, Token)] = &[
+    ("YYYY", Token::Year),
+    ("YY", Token::ShortYear { padded: false }),
+    ("0Y", Token::ShortYear { padded: true }),
+    ("MM", Token::Month { padded: false }),
+    ("0M", Token::Month { padded: true }),
+    ("WW", Token::Week { padded: false }),
+    ("0W", Token::Week { padded: true }),
+    ("DD", Token::Day { padded: false }),
+    ("0D", Token::Day { padded: true }),
+    ("N", Token::N),
+];
+
+impl Scheme {
+    fn tokens(&self) -> Vec<Token> {
+        let mut tokens = Vec::new();
+        let mut rest = self.0.as_str();
+        while let Some(c) = rest.chars().next() {
+            match TOKENS.iter().find(|(text, _)| rest.starts_with(text)) {
+                Some((text, token)) => {
+                    tokens.push(*token);
+                    rest = &rest[text.len()..];
+                }
+                None => {
+                    tokens.push(Token::Literal(c));
+                    rest = &rest[c.len_utf8()..];
+                }
+            }
+        }
+        tokens
+    }
+}
+
+impl FromStr for Scheme {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Scheme> {
+        let scheme = Scheme(s.to_string());
+        let tokens = scheme.tokens();
+        if tokens.iter().filter(|t| **t == Token::N).count() != 1 {
+            bail!("calver scheme '{s}' needs exactly one N for the release number");
+        }
+        if !tokens
+            .iter()
+            .any(|t| !matches!(t, Token::N | Token::Literal(_)))
+        {
+            bail!(
+                "calver scheme '{s}' has no date part, use YYYY, YY, 0Y, MM, 0M, WW, 0W, DD or 0D"
+            );
+        }
+        Ok(scheme)
+    }
+}
+
+impl fmt::Display for Scheme {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Calver {
+    /// The version with `n` as its release number.
+    pub fn render(&self, n: i64) -> String {
+        self.write(self.date, Some(n))
+    }
+
+    /// Move the version to `today`, returning whether that starts a new
+    /// period so the release number has to start over.
+    pub fn advance(&mut self, today: NaiveDate) -> bool {
+        let changed = self.write(self.date, None) != self.write(today, None);
+        self.date = today;
+        changed
+    }
+
+    /// Date component by name, for `{app.year}` in templates.
+    pub fn field(&self, name: &str) -> Option<i64> {
+        let n = match name {
+            "year" => self.year(self.date),
+            "month" => self.date.month() as i32,
+            "week" => self.date.iso_week().week() as i32,
+            "day" => self.date.day() as i32,
+            _ => return None,
+        };
+        Some(n.into())
+    }
+
+    /// Year of `date` as the scheme writes it, which is the ISO week's year
+    /// when it has a week, as 2027-01-01 falls in week 53 of 2026.
+    fn year(&self, date: NaiveDate) -> i32 {
+        let weekly = self
+            .scheme
+            .tokens()
+            .iter()
+            .any(|t| matches!(t, Token::Week { .. }));
+        if weekly {
+            date.iso_week().year()
+        } else {
+            date.year()
+        }
+    }
+
+    /// The scheme written out for `date`, leaving out `N` when there's no
+    /// release number, which is what tells periods apart.
+    fn write(&self, date: NaiveDate, n: Option<i64>) -> String {
+        let padded = |value: i64, pad: bool| {
+            if pad {
+                format!("{value:02}")
+            } else {
+                value.to_string()
+            }
+        };
+        let year = self.year(date);
+        let mut out = String::new();
+        for token in self.scheme.tokens() {
+            match token {
+                Token::Literal(c) => out.push(c),
+                Token::Year => out.push_str(&year.to_string()),
+                Token::ShortYear { padded: pad } => {
+                    out.push_str(&padded(i64::from(year) - 2000, pad))
+                }
+                Token::Month { padded: pad } => out.push_str(&padded(date.month().into(), pad)),
+                Token::Week { padded: pad } => {
+                    out.push_str(&padded(date.iso_week().week().into(), pad))
+                }
+                Token::Day { padded: pad } => out.push_str(&padded(date.day().into(), pad)),
+                Token::N => {
+                    if let Some(n) = n {
+                        out.push_str(&n.to_string())
+                    }
+                }
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bumped(version: &str, part: Part, pre_id: Option<&str>) -> String {
+        let mut v: Semver = version.parse().unwrap();
+        v.bump(part, pre_id).unwrap();
+        v.to_string()
+    }
+
+    #[test]
+    fn parses_and_displays() {
+        for s in ["0.0.0", "1.2.3", "10.20.30-rc.1", "1.0.0-alpha-2.x"] {
+            assert_eq!(s.parse::<Semver>().unwrap().to_string(), s);
+        }
+        assert_eq!("v1.2.3".parse::<Semver>().unwrap().to_string(), "1.2.3");
+        for s in [
+            "1.2",
+            "1.2.3.4",
+            "1.x.3",
+            "1.2.3-",
+            "1.2.3-rc..1",
+            "",
+            "-1.2.3",
+        ] {
+            assert!(s.parse::<Semver>().is_err(), "{s}");
+        }
+    }
+
+    #[test]
+    fn bumps_reset_lower_parts() {
+        assert_eq!(bumped("1.2.3", Part::Major, None), "2.0.0");
+        assert_eq!(bumped("1.2.3", Part::Minor, None), "1.3.0");
+        assert_eq!(bumped("1.2.3", Part::Patch, None), "1.2.4");
+    }
+
+    #[test]
+    fn bumps_release_pre_releases() {
+        assert_eq!(bumped("1.2.4-rc.1", Part::Patch, None), "1.2.4");
+        assert_eq!(bumped("1.3.0-rc.1", Part::Minor, None), "1.3.0");
+        assert_eq!(bumped("1.3.1-rc.1", Part::Minor, None), "1.4.0");
+        assert_eq!(bumped("2.0.0-beta", Part::Major, None), "2.0.0");
+    }
+
+    #[test]
+    fn bumps_pre_releases() {
+        assert_eq!(bumped("1.2.3", Part::Pre, None), "1.2.4-0");
+        assert_eq!(bumped("1.2.3", Part::Pre, Some("rc")), "1.2.4-rc.0");
+        assert_eq!(bumped("1.2.4-rc.0", Part::Pre, None), "1.2.4-rc.1");
+        assert_eq!(bumped("1.2.4-rc.9", Part::Pre, Some("rc")), "1.2.4-rc.10");
+        assert_eq!(bumped("1.2.4-beta", Part::Pre, None), "1.2.4-beta.0");
+        assert_eq!(bumped("1.2.4-beta.3", Part::Pre, Some("rc")), "1.2.4-rc.0");
+        assert_eq!(bumped("1.2.4-7", Part::Pre, None), "1.2.4-8");
+
+        let mut v: Semver = "1.2.3".parse().unwrap();
+        assert!(v.bump(Part::Pre, Some("r c")).is_err());
+    }
+
+    #[test]
+    fn fields() {
+        let v: Semver = "1.2.3-rc.1".parse().unwrap();
+        assert_eq!(v.field("major"), Some(1));
+        assert_eq!(v.field("minor"), Some(2));
+        assert_eq!(v.field("patch"), Some(3));
+        assert_eq!(v.field("pre"), None);
+    }
+
+    fn calver(scheme: &str, date: &str) -> Calver {
+        Calver {
+            scheme: scheme.parse().unwrap(),
+            date: date.parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn calver_schemes() {
+        assert_eq!(calver("YYYY.MM.N", "2026-03-09").render(4), "2026.3.4");
+        assert_eq!(calver("YY.0M.0D-N", "2026-03-09").render(0), "26.03.09-0");
+        assert_eq!(calver("0Y.WW.N", "2006-01-02").render(1), "06.1.1");
+        assert_eq!(calver("vYYYY.0W.N", "2026-10-19").render(2), "v2026.43.2");
+        // Weeks belong to the ISO year, which needn't be the calendar one
+        assert_eq!(calver("YYYY.WW.N", "2027-01-01").render(0), "2026.53.0");
+        assert_eq!(calver("0Y.0W.N", "2024-12-30").render(0), "25.01.0");
+        assert_eq!(calver("YYYY.0M.N", "2027-01-01").render(0), "2027.01.0");
+        assert_eq!(calver("YYYY.WW.N", "2027-01-01").field("year"), Some(2026));
+        assert!("YYYY.MM".parse::<Scheme>().is_err());
+        assert!("N.N".parse::<Scheme>().is_err());
+        assert!("release-N".parse::<Scheme>().is_err());
+    }
+
+    #[test]
+    fn calver_advances_by_period() {
+        let mut v = calver("YYYY.0M.N", "2026-03-09");
+        assert!(!v.advance("2026-03-31".parse().unwrap()));
+        assert!(v.advance("2026-04-01".parse().unwrap()));
+        assert_eq!(v.render(0), "2026.04.0");
+
+        let mut daily = calver("YYYY.MM.DD.N", "2026-04-01");
+        assert!(daily.advance("2026-04-02".parse().unwrap()));
+        assert_eq!(daily.field("day"), Some(2));
+        assert_eq!(daily.field("month"), Some(4));
+        assert_eq!(daily.field("n"), None);
+    }
+}
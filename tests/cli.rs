@@ -205,3 +205,1080 @@ fn bare_invocation_after_nuke_still_works() {
     tally(&home).args(["nuke", "--yes"]).assert().success();
     tally(&home).args(["foo", "add", "1"]).assert().success();
 }
+
+fn write_config(home: &TempDir, contents: &str) {
+    let dir = home.path().join(".tally");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("config.toml"), contents).unwrap();
+}
+
+#[test]
+fn config_defaults_apply_to_new_counters() {
+    let home = TempDir::new().unwrap();
+    write_config(&home, "default_step = 3\ndefault_template = \"v{}\"\n");
+    tally(&home)
+        .args(["foo", "add"])
+        .assert()
+        .success()
+        .stdout("v3\n");
+    tally(&home)
+        .args(["foo", "add"])
+        .assert()
+        .success()
+        .stdout("v6\n");
+}
+
+#[test]
+fn config_database_path_is_used() {
+    let home = TempDir::new().unwrap();
+    write_config(&home, "database = \"~/elsewhere/counters.db\"\n");
+    tally(&home).args(["foo", "add", "2"]).assert().success();
+    assert!(home.path().join("elsewhere/counters.db").exists());
+    assert!(!home.path().join(".tally/tally.db").exists());
+}
+
+#[test]
+fn database_flag_overrides_config() {
+    let home = TempDir::new().unwrap();
+    write_config(&home, "database = \"~/from-config.db\"\n");
+    let db = home.path().join("from-flag.db");
+    tally(&home)
+        .arg("--database")
+        .arg(&db)
+        .args(["foo", "add", "2"])
+        .assert()
+        .success();
+    assert!(db.exists());
+    assert!(!home.path().join("from-config.db").exists());
+}
+
+#[test]
+fn invalid_config_is_reported() {
+    let home = TempDir::new().unwrap();
+    write_config(&home, "stepp = 2\n");
+    tally(&home)
+        .args(["foo"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("config.toml"));
+}
+
+#[test]
+fn strict_mode_refuses_to_create_counters() {
+    let home = TempDir::new().unwrap();
+    write_config(&home, "strict = true\n");
+    tally(&home)
+        .args(["typo", "add"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("does not exist"));
+    tally(&home).args(["typo", "set", "4"]).assert().success();
+    tally(&home)
+        .args(["typo", "add"])
+        .assert()
+        .success()
+        .stdout("5\n");
+}
+
+#[test]
+fn strict_flag_refuses_to_create_counters() {
+    let home = TempDir::new().unwrap();
+    tally(&home)
+        .args(["--strict", "typo"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("does not exist"));
+}
+
+#[test]
+fn list_json_format() {
+    let home = TempDir::new().unwrap();
+    tally(&home).args(["foo", "add", "4"]).assert().success();
+    let output = tally(&home)
+        .args(["list", "--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let rows: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    let foo = rows
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|r| r["name"] == "foo")
+        .expect("foo row present");
+    assert_eq!(foo["count"], 4);
+    assert_eq!(foo["default"], false);
+}
+
+#[test]
+fn config_format_applies_to_list() {
+    let home = TempDir::new().unwrap();
+    write_config(&home, "format = \"json\"\n");
+    tally(&home)
+        .args(["list"])
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with("["));
+}
+
+#[test]
+fn completions_prints_registration_script() {
+    let home = TempDir::new().unwrap();
+    for shell in ["bash", "zsh", "fish", "elvish", "powershell"] {
+        tally(&home)
+            .args(["completions", shell])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("COMPLETE"));
+    }
+    assert!(!home.path().join(".tally/tally.db").exists());
+}
+
+#[test]
+fn completion_offers_counter_names() {
+    let home = TempDir::new().unwrap();
+    tally(&home)
+        .args(["build-number", "add"])
+        .assert()
+        .success();
+    tally(&home)
+        .env("COMPLETE", "fish")
+        .args(["--", "tally", "bui"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("build-number"));
+}
+
+#[test]
+fn man_prints_roff_page() {
+    let home = TempDir::new().unwrap();
+    tally(&home)
+        .arg("man")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(".TH tally 1"));
+}
+
+#[test]
+fn man_out_dir_writes_page_per_subcommand() {
+    let home = TempDir::new().unwrap();
+    let out = home.path().join("man1");
+    tally(&home)
+        .arg("man")
+        .arg("--out-dir")
+        .arg(&out)
+        .assert()
+        .success();
+    assert!(out.join("tally.1").exists());
+    assert!(out.join("tally-add.1").exists());
+    assert!(out.join("tally-list.1").exists());
+}
+
+#[test]
+fn template_format_specifiers() {
+    let home = TempDir::new().unwrap();
+    tally(&home).args(["build", "set", "42"]).assert().success();
+    tally(&home)
+        .args(["release", "set", "--template", "v1.0.{build:04}"])
+        .assert()
+        .success();
+    tally(&home)
+        .args(["release"])
+        .assert()
+        .success()
+        .stdout("v1.0.0042\n");
+}
+
+#[test]
+fn template_arithmetic() {
+    let home = TempDir::new().unwrap();
+    tally(&home).args(["hits", "set", "3"]).assert().success();
+    tally(&home).args(["total", "set", "4"]).assert().success();
+    tally(&home)
+        .args(["rate", "set", "--template", "{hits / total * 100:.1}%"])
+        .assert()
+        .success();
+    tally(&home)
+        .args(["rate"])
+        .assert()
+        .success()
+        .stdout("75.0%\n");
+}
+
+#[test]
+fn template_division_by_zero_fails() {
+    let home = TempDir::new().unwrap();
+    tally(&home)
+        .args(["rate", "set", "--template", "{count / 0}"])
+        .assert()
+        .success();
+    tally(&home)
+        .args(["rate"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("division by zero"));
+}
+
+#[test]
+fn template_conditionals_and_plurals() {
+    let home = TempDir::new().unwrap();
+    tally(&home)
+        .args([
+            "errors",
+            "set",
+            "--template",
+            r#"{if count > 0}FAIL{else}ok{end}: {count} {plural count "error" "errors"}"#,
+        ])
+        .assert()
+        .success();
+    tally(&home)
+        .args(["errors"])
+        .assert()
+        .success()
+        .stdout("ok: 0 errors\n");
+    tally(&home)
+        .args(["errors", "add"])
+        .assert()
+        .success()
+        .stdout("FAIL: 1 error\n");
+}
+
+#[test]
+fn set_rejects_unparseable_template() {
+    let home = TempDir::new().unwrap();
+    tally(&home)
+        .args(["foo", "set", "--template", "${HOME"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("invalid template"));
+    tally(&home)
+        .args(["list", "--no-headers"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("foo").not());
+}
+
+#[test]
+fn template_escaped_braces() {
+    let home = TempDir::new().unwrap();
+    tally(&home)
+        .args(["foo", "set", "3", "--template", r#"{{"foo": {}}}"#])
+        .assert()
+        .success();
+    tally(&home)
+        .args(["foo"])
+        .assert()
+        .success()
+        .stdout("{\"foo\": 3}\n");
+}
+
+#[test]
+fn template_date_and_name_builtins() {
+    let home = TempDir::new().unwrap();
+    tally(&home)
+        .args([
+            "release",
+            "set",
+            "41",
+            "--template",
+            "{counter_name}-{date:%Y%m%d}-{:04}",
+        ])
+        .assert()
+        .success();
+    tally(&home)
+        .env("SOURCE_DATE_EPOCH", "1792281600")
+        .args(["release", "add"])
+        .assert()
+        .success()
+        .stdout("release-20261018-0042\n");
+}
+
+#[test]
+fn template_env_builtin() {
+    let home = TempDir::new().unwrap();
+    tally(&home)
+        .args(["foo", "set", "--template", "{env:TALLY_TEST_USER}/{}"])
+        .assert()
+        .success();
+    tally(&home)
+        .env("TALLY_TEST_USER", "alice")
+        .args(["foo"])
+        .assert()
+        .success()
+        .stdout("alice/0\n");
+    tally(&home)
+        .env_remove("TALLY_TEST_USER")
+        .args(["foo"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("TALLY_TEST_USER"));
+}
+
+#[test]
+fn check_template_reports_problems() {
+    let home = TempDir::new().unwrap();
+    tally(&home).args(["build", "set", "3"]).assert().success();
+    tally(&home)
+        .args(["release", "set", "--template", "{build:03}-{notes}"])
+        .assert()
+        .success();
+    tally(&home)
+        .args(["notes", "set", "--template", "{release}{ghost}"])
+        .assert()
+        .success();
+
+    tally(&home)
+        .args(["build", "check-template"])
+        .assert()
+        .success()
+        .stdout("template of counter 'build' is ok\n");
+    tally(&home)
+        .args(["release", "check-template"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "reference cycle notes -> release -> notes",
+        ))
+        .stderr(predicate::str::contains(
+            "counter 'notes' uses missing counter 'ghost'",
+        ));
+    tally(&home)
+        .args(["ghost", "check-template"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("does not exist"));
+}
+
+#[test]
+fn graph_prints_dependencies() {
+    let home = TempDir::new().unwrap();
+    tally(&home).args(["build", "set", "3"]).assert().success();
+    tally(&home)
+        .args(["release", "set", "--template", "v{build:03}"])
+        .assert()
+        .success();
+    tally(&home)
+        .args(["graph"])
+        .assert()
+        .success()
+        .stdout("build\nrelease -> build\ntally\n");
+    tally(&home)
+        .args(["graph", "--dot"])
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with("digraph tally {\n"))
+        .stdout(predicate::str::contains(
+            "\"release\" -> \"build\" [style=dashed];",
+        ));
+}
+
+#[test]
+fn template_named_formatters() {
+    let home = TempDir::new().unwrap();
+    tally(&home)
+        .args([
+            "edition",
+            "set",
+            "2",
+            "--template",
+            "{:ordinal} edition, volume {:roman}",
+        ])
+        .assert()
+        .success();
+    tally(&home)
+        .args(["edition"])
+        .assert()
+        .success()
+        .stdout("2nd edition, volume II\n");
+}
+
+#[test]
+fn render_ad_hoc_template() {
+    let home = TempDir::new().unwrap();
+    tally(&home).args(["build", "set", "12"]).assert().success();
+    tally(&home)
+        .args(["patch", "set", "4", "--template", "p{}"])
+        .assert()
+        .success();
+
+    tally(&home)
+        .args(["render", "{build}-{patch:03}"])
+        .assert()
+        .success()
+        .stdout("12-004\n");
+    tally(&home)
+        .args(["patch", "render", "{counter_name} {:02}"])
+        .assert()
+        .success()
+        .stdout("patch 04\n");
+    tally(&home)
+        .args(["patch"])
+        .assert()
+        .success()
+        .stdout("p4\n");
+    tally(&home)
+        .args(["render", "{}"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("no counter is being rendered"));
+    tally(&home)
+        .args(["ghost", "render", "{}"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("does not exist"));
+}
+
+#[test]
+fn semver_counters_bump() {
+    let home = TempDir::new().unwrap();
+    tally(&home)
+        .args(["app", "set", "--semver", "1.4.2", "--template", "v{}"])
+        .assert()
+        .success();
+    tally(&home)
+        .args(["app", "bump", "minor"])
+        .assert()
+        .success()
+        .stdout("v1.5.0\n");
+    tally(&home)
+        .args(["app", "bump", "pre", "--id", "rc"])
+        .assert()
+        .success()
+        .stdout("v1.5.1-rc.0\n");
+    tally(&home)
+        .args(["app", "bump", "patch"])
+        .assert()
+        .success()
+        .stdout("v1.5.1\n");
+    tally(&home)
+        .args(["render", "{app.major}.{app.minor}"])
+        .assert()
+        .success()
+        .stdout("1.5\n");
+    tally(&home)
+        .args(["app", "add"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("bump"));
+}
+
+#[test]
+fn bump_creates_semver_counter() {
+    let home = TempDir::new().unwrap();
+    tally(&home)
+        .args(["lib", "bump", "major"])
+        .assert()
+        .success()
+        .stdout("1.0.0\n");
+    tally(&home).args(["plain", "add"]).assert().success();
+    tally(&home)
+        .args(["plain", "bump", "major"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("isn't a version"));
+}
+
+#[test]
+fn calver_counters_reset_each_period() {
+    let home = TempDir::new().unwrap();
+    let september = "1790769600";
+    let october = "1790856000";
+    let november = "1793534400";
+    let december = "1796126400";
+    tally(&home)
+        .env("SOURCE_DATE_EPOCH", september)
+        .args(["release", "set", "--calver", "YYYY.0M.N"])
+        .assert()
+        .success();
+    for expected in ["2026.09.1\n", "2026.09.2\n"] {
+        tally(&home)
+            .env("SOURCE_DATE_EPOCH", september)
+            .args(["release", "add"])
+            .assert()
+            .success()
+            .stdout(expected);
+    }
+    tally(&home)
+        .env("SOURCE_DATE_EPOCH", october)
+        .args(["release", "add"])
+        .assert()
+        .success()
+        .stdout("2026.10.1\n");
+    tally(&home)
+        .env("SOURCE_DATE_EPOCH", october)
+        .args(["render", "{release.month}-{release.n}"])
+        .assert()
+        .success()
+        .stdout("10-1\n");
+    tally(&home)
+        .env("SOURCE_DATE_EPOCH", november)
+        .args(["release", "add", "0"])
+        .assert()
+        .success()
+        .stdout("2026.11.0\n");
+    tally(&home)
+        .env("SOURCE_DATE_EPOCH", december)
+        .args(["release", "add", "3"])
+        .assert()
+        .success()
+        .stdout("2026.12.3\n");
+    tally(&home)
+        .args(["release", "set", "--calver", "YYYY.0M"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("exactly one N"));
+    tally(&home)
+        .args(["release", "bump", "minor"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("calendar version"));
+}
+
+#[test]
+fn scoped_counters_per_branch() {
+    let home = TempDir::new().unwrap();
+    let repo = TempDir::new().unwrap();
+    let head = repo.path().join(".git/HEAD");
+    std::fs::create_dir_all(head.parent().unwrap()).unwrap();
+    let checkout = |branch: &str| {
+        std::fs::write(&head, format!("ref: refs/heads/{branch}\n")).unwrap();
+    };
+    let build = |args: &[&str]| {
+        let mut cmd = tally(&home);
+        cmd.current_dir(repo.path())
+            .arg("build")
+            .args(args)
+            .args(["--scope", "git-branch"]);
+        cmd
+    };
+
+    checkout("main");
+    build(&["add"]).assert().success().stdout("1\n");
+    build(&["add"]).assert().success().stdout("2\n");
+    checkout("topic");
+    build(&["add"]).assert().success().stdout("1\n");
+    checkout("main");
+    build(&[]).assert().success().stdout("2\n");
+    std::fs::write(&head, "0123456789abcdef0123456789abcdef01234567\n").unwrap();
+    build(&["add"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("is detached"));
+    checkout("main");
+    tally(&home)
+        .current_dir(repo.path())
+        .args(["build", "add", "--scope", "repo"])
+        .assert()
+        .success()
+        .stdout("1\n");
+
+    // One counter however many scopes it has
+    tally(&home)
+        .args(["list", "--no-headers"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("build").count(1));
+
+    let outside = TempDir::new().unwrap();
+    if !outside.path().ancestors().any(|d| d.join(".git").exists()) {
+        tally(&home)
+            .current_dir(outside.path())
+            .args(["build", "add", "--scope", "repo"])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("isn't inside a git repository"));
+    }
+}
+
+#[test]
+fn gauge_counters_keep_statistics() {
+    let home = TempDir::new().unwrap();
+    tally(&home)
+        .args(["queue", "set", "--gauge", "4"])
+        .assert()
+        .success();
+    for depth in ["10", "1"] {
+        tally(&home)
+            .args(["queue", "set", depth])
+            .assert()
+            .success();
+    }
+    tally(&home)
+        .args(["queue", "stats"])
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::contains("Last     1")
+                .and(predicate::str::contains("Max      10"))
+                .and(predicate::str::contains("Avg      5.00"))
+                .and(predicate::str::contains("Samples  3")),
+        );
+    tally(&home)
+        .args([
+            "render",
+            "{queue} of {queue.max}, {queue.avg:.1} on average",
+        ])
+        .assert()
+        .success()
+        .stdout("1 of 10, 5.0 on average\n");
+    tally(&home)
+        .args(["queue", "add"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("is a gauge"));
+}
+
+#[test]
+fn stats_summarize_history() {
+    let home = TempDir::new().unwrap();
+    let at = |epoch: &str, args: &[&str]| {
+        let mut cmd = tally(&home);
+        cmd.env("SOURCE_DATE_EPOCH", epoch).arg("builds").args(args);
+        cmd
+    };
+    at("1792144800", &["add"]).assert().success();
+    at("1792144800", &["add"]).assert().success();
+    at("1792227600", &["add", "4"]).assert().success();
+
+    at("1792324800", &["stats", "--since", "3d", "--bucket", "1d"])
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::contains("Changes  3")
+                .and(predicate::str::contains("Total    6"))
+                .and(predicate::str::contains("Mean     2.00"))
+                .and(predicate::str::contains("P90      4"))
+                .and(predicate::str::contains("Rate     2.00/day"))
+                .and(predicate::str::contains("2026-10-15 00:00  0    0"))
+                .and(predicate::str::contains("2026-10-16 00:00  2    2"))
+                .and(predicate::str::contains("2026-10-17 00:00  4    1")),
+        );
+
+    let output = at(
+        "1792324800",
+        &["stats", "--since", "2d", "--format", "json"],
+    )
+    .output()
+    .unwrap();
+    let stats: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(stats["changes"], 1);
+    assert_eq!(stats["p50"], 4);
+    assert!(stats.get("buckets").is_none());
+
+    at("1792324800", &["stats", "--since", "soon"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("invalid duration"));
+}
+
+#[test]
+fn chart_draws_history() {
+    let home = TempDir::new().unwrap();
+    let at = |epoch: &str, args: &[&str]| {
+        let mut cmd = tally(&home);
+        cmd.env("SOURCE_DATE_EPOCH", epoch).arg("builds").args(args);
+        cmd
+    };
+    at("1792144800", &["add"]).assert().success();
+    at("1792144800", &["add"]).assert().success();
+    at("1792227600", &["add", "4"]).assert().success();
+
+    at("1792324800", &["chart", "--since", "3d", "--width", "6"])
+        .assert()
+        .success()
+        .stdout(
+            "2026-10-15 00:00          0\n\
+             2026-10-16 00:00  ██      2\n\
+             2026-10-17 00:00  ██████  6\n\
+             2026-10-18 00:00  ██████  6\n",
+        );
+    at("1792324800", &["chart", "--since", "3d", "--spark"])
+        .assert()
+        .success()
+        .stdout("▁▃██\n");
+    at("1792324800", &["render", "{builds:spark} {builds}"])
+        .assert()
+        .success()
+        .stdout("▁▂█ 6\n");
+}
+
+#[test]
+fn formula_counters_follow_their_inputs() {
+    let home = TempDir::new().unwrap();
+    tally(&home).args(["passed", "set", "3"]).assert().success();
+    tally(&home).args(["total", "set", "4"]).assert().success();
+    tally(&home)
+        .args(["ratio", "define", "{passed} / {total}"])
+        .assert()
+        .success();
+    tally(&home)
+        .args(["ratio"])
+        .assert()
+        .success()
+        .stdout("0.75\n");
+    tally(&home).args(["passed", "add"]).assert().success();
+    tally(&home)
+        .args(["ratio"])
+        .assert()
+        .success()
+        .stdout("1\n");
+    tally(&home)
+        .args(["list", "--no-headers"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("ratio   = 1"));
+    tally(&home)
+        .args(["ratio", "add"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("is a formula"));
+    tally(&home)
+        .args(["ratio", "define", "{passed} /"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("invalid formula"));
+
+    tally(&home)
+        .args(["a", "define", "{b} + 1"])
+        .assert()
+        .success();
+    tally(&home)
+        .args(["b", "define", "{a} + 1"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("formula cycle a -> b -> a"));
+    tally(&home)
+        .args(["c", "define", "{c} + 1"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("formula cycle c -> c"));
+    tally(&home).args(["a"]).assert().success().stdout("1\n");
+    tally(&home).arg("graph").assert().success();
+}
+
+#[cfg(unix)]
+#[test]
+fn hooks_that_fail_to_start_leave_the_change() {
+    let home = TempDir::new().unwrap();
+    for command in ["true", "true"] {
+        tally(&home)
+            .args(["x", "on", "--every", "1", "--run", command])
+            .assert()
+            .success();
+    }
+    // Without a PATH there's no shell to run them
+    tally(&home)
+        .env("PATH", "")
+        .args(["x", "add"])
+        .assert()
+        .success()
+        .stdout("1\n")
+        .stderr(
+            predicate::str::contains("failed to run hook 1")
+                .and(predicate::str::contains("failed to run hook 2")),
+        );
+}
+
+#[cfg(unix)]
+#[test]
+fn hooks_run_when_thresholds_are_crossed() {
+    let home = TempDir::new().unwrap();
+    let log = home.path().join("hook.log");
+    let run = format!(
+        "echo \"$TALLY_COUNTER $TALLY_PREVIOUS $TALLY_VALUE $TALLY_TRIGGER\" >> {}",
+        log.display()
+    );
+    tally(&home)
+        .args(["retries", "on", "--above", "2", "--run", &run])
+        .assert()
+        .success();
+    tally(&home)
+        .args(["retries", "on"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("1   above 2  echo"));
+    for _ in 0..4 {
+        tally(&home).args(["retries", "add"]).assert().success();
+    }
+
+    // Hooks run in the background, give it a moment
+    let mut logged = String::new();
+    for _ in 0..100 {
+        logged = std::fs::read_to_string(&log).unwrap_or_default();
+        if !logged.is_empty() {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(50));
+    }
+    assert_eq!(logged, "retries 2 3 above 2\n");
+
+    tally(&home)
+        .args(["retries", "on", "--remove", "1"])
+        .assert()
+        .success();
+    tally(&home)
+        .args(["retries", "on", "--remove", "1"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("has no hook 1"));
+    tally(&home)
+        .args(["retries", "on", "--above", "2"])
+        .assert()
+        .failure();
+}
+
+#[test]
+fn watch_prints_changes() {
+    use std::io::{BufRead, BufReader};
+    use std::process::Stdio;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    let home = TempDir::new().unwrap();
+    tally(&home).args(["builds", "set", "5"]).assert().success();
+    tally(&home).args(["other", "set", "1"]).assert().success();
+
+    let mut watcher = std::process::Command::new(assert_cmd::cargo::cargo_bin("tally"))
+        .env("HOME", home.path())
+        .args(["watch", "builds", "--interval", "0.05", "--format", "json"])
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let (lines, received) = mpsc::channel();
+    let stdout = watcher.stdout.take().unwrap();
+    std::thread::spawn(move || {
+        for line in BufReader::new(stdout).lines() {
+            if lines.send(line.unwrap()).is_err() {
+                break;
+            }
+        }
+    });
+    let next = || received.recv_timeout(Duration::from_secs(10)).unwrap();
+
+    assert_eq!(next(), r#"{"deleted":false,"name":"builds","value":"5"}"#);
+    tally(&home).args(["other", "add"]).assert().success();
+    tally(&home).args(["builds", "add"]).assert().success();
+    assert_eq!(next(), r#"{"deleted":false,"name":"builds","value":"6"}"#);
+    tally(&home).args(["builds", "delete"]).assert().success();
+    assert_eq!(next(), r#"{"deleted":true,"name":"builds","value":null}"#);
+
+    watcher.kill().unwrap();
+    watcher.wait().unwrap();
+}
+
+#[test]
+fn top_needs_a_terminal() {
+    let home = TempDir::new().unwrap();
+    tally(&home)
+        .arg("top")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("top needs a terminal"));
+}
+
+#[cfg(unix)]
+#[test]
+fn daemon_leaves_other_files_alone() {
+    let home = TempDir::new().unwrap();
+    let notes = home.path().join("notes.txt");
+    std::fs::write(&notes, "precious\n").unwrap();
+    tally(&home)
+        .args(["serve", "--socket"])
+        .arg(&notes)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("exists and is not a socket"));
+    assert_eq!(std::fs::read_to_string(&notes).unwrap(), "precious\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn daemon_answers_over_a_socket() {
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::net::UnixStream;
+    use std::time::{Duration, Instant};
+
+    let home = TempDir::new().unwrap();
+    tally(&home).args(["builds", "set", "0"]).assert().success();
+    tally(&home)
+        .args(["who", "set", "--template", "{env:WHO}{}"])
+        .assert()
+        .success();
+
+    let socket = home.path().join(".tally").join("tally.sock");
+    let mut daemon = std::process::Command::new(assert_cmd::cargo::cargo_bin("tally"))
+        .env("HOME", home.path())
+        .env("WHO", "daemon-")
+        .arg("serve")
+        .spawn()
+        .unwrap();
+    let start = Instant::now();
+    while UnixStream::connect(&socket).is_err() {
+        assert!(
+            start.elapsed() < Duration::from_secs(10),
+            "daemon didn't start"
+        );
+        std::thread::sleep(Duration::from_millis(20));
+    }
+
+    tally(&home)
+        .args(["builds", "add"])
+        .assert()
+        .success()
+        .stdout("1\n");
+    // Templates reading the environment are left to the client
+    tally(&home)
+        .env("WHO", "client-")
+        .args(["who", "add"])
+        .assert()
+        .success()
+        .stdout("client-1\n");
+    tally(&home)
+        .args(["missing", "add", "--strict"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("does not exist"));
+    tally(&home)
+        .arg("serve")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("already listening"));
+
+    let mut stream = UnixStream::connect(&socket).unwrap();
+    writeln!(
+        stream,
+        r#"{{"op": "add", "name": "builds", "amount": 2, "raw": true}}"#
+    )
+    .unwrap();
+    let mut reply = String::new();
+    BufReader::new(&stream).read_line(&mut reply).unwrap();
+    assert_eq!(reply, "{\"value\":\"3\"}\n");
+    writeln!(stream, r#"{{"op": "read", "name": "who"}}"#).unwrap();
+    let mut reply = String::new();
+    BufReader::new(&stream).read_line(&mut reply).unwrap();
+    assert_eq!(reply, "{\"local\":true}\n");
+
+    // Other commands still get the lock while the daemon runs
+    tally(&home)
+        .args(["list"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("builds"));
+
+    daemon.kill().unwrap();
+    daemon.wait().unwrap();
+    tally(&home)
+        .args(["builds"])
+        .assert()
+        .success()
+        .stdout("3\n");
+}
+
+#[test]
+fn http_api_serves_counters() {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpStream;
+    use std::process::Stdio;
+
+    let home = TempDir::new().unwrap();
+    tally(&home).args(["builds", "set", "5"]).assert().success();
+
+    let mut server = std::process::Command::new(assert_cmd::cargo::cargo_bin("tally"))
+        .env("HOME", home.path())
+        .env("TALLY_TOKEN", "secret")
+        .args(["serve", "--http", "127.0.0.1:0"])
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut line = String::new();
+    BufReader::new(server.stderr.take().unwrap())
+        .read_line(&mut line)
+        .unwrap();
+    let address = line
+        .trim()
+        .strip_prefix("tally: serving on http://")
+        .unwrap();
+
+    let request = |method: &str, path: &str, token: &str, body: &str| {
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(
+            stream,
+            "{method} {path} HTTP/1.1\r\nHost: {address}\r\nAuthorization: Bearer {token}\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let status: u16 = head.split(' ').nth(1).unwrap().parse().unwrap();
+        (
+            status,
+            serde_json::from_str::<serde_json::Value>(body).unwrap(),
+        )
+    };
+
+    let (status, _) = request("GET", "/counters", "wrong", "");
+    assert_eq!(status, 401);
+
+    let (status, counters) = request("GET", "/counters", "secret", "");
+    assert_eq!(status, 200);
+    assert_eq!(counters.as_array().unwrap().len(), 2);
+
+    let (status, builds) = request("POST", "/counters/builds/add", "secret", r#"{"amount": 3}"#);
+    assert_eq!(status, 200);
+    assert_eq!(builds["count"], 8);
+
+    let (status, deploys) = request("POST", "/counters/deploys", "secret", r#"{"step": 2}"#);
+    assert_eq!(status, 200);
+    assert_eq!(deploys["step"], 2);
+
+    let (status, error) = request("GET", "/counters/missing", "secret", "");
+    assert_eq!(status, 404);
+    assert_eq!(error["error"], "counter 'missing' does not exist");
+
+    // Commands still work while the server runs
+    tally(&home)
+        .args(["builds", "add"])
+        .assert()
+        .success()
+        .stdout("9\n");
+
+    server.kill().unwrap();
+    server.wait().unwrap();
+}
+
+#[test]
+fn changes_too_big_to_record_are_refused() {
+    let home = TempDir::new().unwrap();
+    tally(&home)
+        .args(["x", "set", "--", "-1"])
+        .assert()
+        .success();
+    tally(&home)
+        .args(["x", "set", "9223372036854775807"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("can't go from -1"));
+    tally(&home).arg("x").assert().success().stdout("-1\n");
+}
+
+#[test]
+fn counts_stay_put_when_history_fails() {
+    let home = TempDir::new().unwrap();
+    tally(&home).args(["x", "set", "5"]).assert().success();
+    let db = sqlite::open(home.path().join(".tally").join("tally.db")).unwrap();
+    db.execute(
+        "CREATE TRIGGER full BEFORE INSERT ON history BEGIN SELECT RAISE(FAIL, 'history is full'); END;",
+    )
+    .unwrap();
+    drop(db);
+    tally(&home)
+        .args(["x", "add"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("history is full"));
+    tally(&home).arg("x").assert().success().stdout("5\n");
+}
@@ -0,0 +1,76 @@
+use std::path::Path;
+use std::process::ExitCode;
+use std::sync::Once;
+use tempfile::TempDir;
+
+/// Drive the CLI in-process against the database in `dir`, returning the
+/// exit code and captured stdout and stderr.
+fn run(dir: &TempDir, args: &[&str]) -> (ExitCode, String, String) {
+    // Keep the developer's own config out of the picture
+    static ISOLATE: Once = Once::new();
+    ISOLATE.call_once(|| std::env::set_var("TALLY_CONFIG", "/nonexistent/config.toml"));
+
+    let database = dir.path().join("tally.db");
+    let mut argv = vec!["tally", "--database", path(&database)];
+    argv.extend_from_slice(args);
+
+    let mut stdout = Vec::new();
+    let mut stderr = Vec::new();
+    let code = tally_cli::run(argv, &mut stdout, &mut stderr);
+    (
+        code,
+        String::from_utf8(stdout).unwrap(),
+        String::from_utf8(stderr).unwrap(),
+    )
+}
+
+fn path(p: &Path) -> &str {
+    p.to_str().unwrap()
+}
+
+#[test]
+fn add_writes_new_count_to_stdout() {
+    let dir = TempDir::new().unwrap();
+    let (code, stdout, stderr) = run(&dir, &["foo", "add", "3"]);
+    assert_eq!(code, ExitCode::SUCCESS);
+    assert_eq!(stdout, "3\n");
+    assert_eq!(stderr, "");
+
+    let (_, stdout, _) = run(&dir, &["foo", "sub"]);
+    assert_eq!(stdout, "2\n");
+}
+
+#[test]
+fn parse_errors_go_to_stderr() {
+    let dir = TempDir::new().unwrap();
+    let (code, stdout, stderr) = run(&dir, &["foo", "add", "lots"]);
+    assert_eq!(code, ExitCode::from(2));
+    assert_eq!(stdout, "");
+    assert!(stderr.contains("invalid value 'lots'"), "{stderr:?}");
+}
+
+#[test]
+fn help_goes_to_stdout() {
+    let dir = TempDir::new().unwrap();
+    let (code, stdout, stderr) = run(&dir, &["--help"]);
+    assert_eq!(code, ExitCode::SUCCESS);
+    assert!(stdout.contains("Usage: tally"), "{stdout:?}");
+    assert_eq!(stderr, "");
+}
+
+#[test]
+fn command_errors_fail_with_message() {
+    let dir = TempDir::new().unwrap();
+    let (code, stdout, stderr) = run(&dir, &["--strict", "ghost", "add"]);
+    assert_eq!(code, ExitCode::FAILURE);
+    assert_eq!(stdout, "");
+    assert!(stderr.contains("does not exist"), "{stderr:?}");
+}
+
+#[test]
+fn set_accepts_negative_count() {
+    let dir = TempDir::new().unwrap();
+    run(&dir, &["foo", "set", "-5"]);
+    let (_, stdout, _) = run(&dir, &["foo"]);
+    assert_eq!(stdout, "-5\n");
+}